@@ -8,14 +8,8 @@ struct Args {
     output_filename: PathBuf,
 }
 
-fn main() {
-    let Args {
-        input_filename,
-        output_filename,
-    } = clap::Parser::parse();
-
-    let output = std::fs::read_to_string(&input_filename)
-        .unwrap_or_else(|_| panic!("Failed to read {}", input_filename.display()))
+fn import_events(input: &str) -> String {
+    input
         .lines()
         .map(str::trim)
         .filter(|line| !line.is_empty())
@@ -30,8 +24,60 @@ fn main() {
 
             (!title.is_empty()).then(|| format!("{day} {month} {title}\r\n"))
         })
-        .collect::<String>();
+        .collect()
+}
+
+fn main() {
+    let Args {
+        input_filename,
+        output_filename,
+    } = clap::Parser::parse();
+
+    let output = import_events(
+        &std::fs::read_to_string(&input_filename)
+            .unwrap_or_else(|_| panic!("Failed to read {}", input_filename.display())),
+    );
 
     std::fs::write(&output_filename, output)
         .unwrap_or_else(|_| panic!("Failed to write output to {}", output_filename.display()));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::import_events;
+
+    #[test]
+    fn converts_tab_separated_dates() {
+        assert_eq!(
+            import_events("07-Apr\tWorld Health Day\n25-Dec\tChristmas Day\n"),
+            "7 Apr World Health Day\r\n25 Dec Christmas Day\r\n"
+        );
+    }
+
+    #[test]
+    fn trims_whitespace() {
+        assert_eq!(
+            import_events("  01 - January \t  New Year's Day  \r\n"),
+            "1 January New Year's Day\r\n"
+        );
+    }
+
+    #[test]
+    fn skips_blank_lines_and_untitled_dates() {
+        assert_eq!(
+            import_events("\n   \n01-May\t\n02-May\t   \n03-May\tEvent\n"),
+            "3 May Event\r\n"
+        );
+    }
+
+    #[test]
+    fn skips_lines_without_a_tab() {
+        assert_eq!(import_events("Heading\n04-Jul\tEvent\n"), "4 Jul Event\r\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "- not found")]
+    fn rejects_dates_without_a_dash() {
+        import_events("4 July\tEvent\n");
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
-    collections::HashMap,
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
     fmt,
     io::{BufRead, Write},
     str::FromStr,
@@ -7,7 +8,7 @@ use std::{
 
 use anyhow::Context;
 use askama::Template;
-use chrono::{Datelike, Month, Weekday};
+use chrono::{Datelike, Month, Timelike, Weekday};
 use druid::{
     im::Vector,
     text::ArcStr,
@@ -18,6 +19,41 @@ use itertools::Itertools;
 
 const HTTP_RESPONSE_HEADER: &str = include_str!("response.http");
 
+/// The background class of weekends, also used for the dates of `non-working` groups
+const SHADED_BACKGROUND_CLASS: &str = "shadedBackground";
+
+/// The bundled holiday packs, which can be included with `!holidays id`
+const HOLIDAY_PACKS: [(&str, &str); 22] = [
+    ("gb-eng", include_str!("../holidays/gb-eng.txt")),
+    ("gb-sct", include_str!("../holidays/gb-sct.txt")),
+    ("gb-nir", include_str!("../holidays/gb-nir.txt")),
+    ("us", include_str!("../holidays/us.txt")),
+    ("de", include_str!("../holidays/de.txt")),
+    ("de-bw", include_str!("../holidays/de-bw.txt")),
+    ("de-by", include_str!("../holidays/de-by.txt")),
+    ("de-be", include_str!("../holidays/de-be.txt")),
+    ("de-bb", include_str!("../holidays/de-bb.txt")),
+    ("de-hb", include_str!("../holidays/de-hb.txt")),
+    ("de-hh", include_str!("../holidays/de-hh.txt")),
+    ("de-he", include_str!("../holidays/de-he.txt")),
+    ("de-mv", include_str!("../holidays/de-mv.txt")),
+    ("de-ni", include_str!("../holidays/de-ni.txt")),
+    ("de-nw", include_str!("../holidays/de-nw.txt")),
+    ("de-rp", include_str!("../holidays/de-rp.txt")),
+    ("de-sl", include_str!("../holidays/de-sl.txt")),
+    ("de-sn", include_str!("../holidays/de-sn.txt")),
+    ("de-st", include_str!("../holidays/de-st.txt")),
+    ("de-sh", include_str!("../holidays/de-sh.txt")),
+    ("de-th", include_str!("../holidays/de-th.txt")),
+    ("fr", include_str!("../holidays/fr.txt")),
+];
+
+/// The bundled themes, which are listed before the themes in the user's themes directory
+const THEMES: [(&str, &str); 2] = [
+    ("Greyscale", include_str!("../themes/greyscale.toml")),
+    ("Elegant", include_str!("../themes/elegant.toml")),
+];
+
 const MONTHS: [Month; 12] = [
     Month::January,
     Month::February,
@@ -34,25 +70,14 @@ const MONTHS: [Month; 12] = [
 ];
 
 fn days_in_month(year: i32, month: Month) -> u32 {
-    match month {
-        Month::April | Month::June | Month::September | Month::November => 30,
-
-        Month::January
-        | Month::March
-        | Month::May
-        | Month::July
-        | Month::August
-        | Month::October
-        | Month::December => 31,
-
-        Month::February => {
-            if (year % 4) == 0 {
-                29
-            } else {
-                28
-            }
-        }
-    }
+    let first_of_month =
+        chrono::NaiveDate::from_ymd_opt(year, month.number_from_month(), 1).unwrap();
+
+    let first_of_next_month = first_of_month
+        .checked_add_months(chrono::Months::new(1))
+        .unwrap();
+
+    (first_of_next_month - first_of_month).num_days() as u32
 }
 
 trait WeekdayExt: Sized + 'static {
@@ -128,20 +153,305 @@ impl fmt::Display for GroupId {
     }
 }
 
+/// A start time, and optionally an end time, e.g. `14:30` or `14:30-15:15`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct EventTime {
+    start: chrono::NaiveTime,
+    end: Option<chrono::NaiveTime>,
+}
+
+impl EventTime {
+    /// Splits a time from the start of an event title, e.g. `14:30-15:15 Dentist`.
+    ///
+    /// The first word is only a time if it looks like `HH:MM` or `HH:MM-HH:MM`, so that titles such as `10:30am Brunch` are kept whole.
+    /// An end time before the start time is on the following day
+    fn split_from_title(title: &str) -> anyhow::Result<(Option<Self>, &str)> {
+        let title = title.trim();
+
+        let (time, rest) = title.split_once([' ', '\t']).unwrap_or((title, ""));
+
+        let looks_like_time = |time: &str| {
+            time.split_once(':').is_some_and(|(hours, minutes)| {
+                (1..=2).contains(&hours.len())
+                    && minutes.len() == 2
+                    && hours
+                        .chars()
+                        .chain(minutes.chars())
+                        .all(|c| c.is_ascii_digit())
+            })
+        };
+
+        let is_time = match time.split_once('-') {
+            Some((start, end)) => looks_like_time(start) && looks_like_time(end),
+            None => looks_like_time(time),
+        };
+
+        if !is_time {
+            return Ok((None, title));
+        }
+
+        let parse_time = |time: &str| {
+            chrono::NaiveTime::parse_from_str(time, "%H:%M")
+                .with_context(|| format!("Invalid time {time}"))
+        };
+
+        let (start, end) = match time.split_once('-') {
+            Some((start, end)) => (parse_time(start)?, Some(parse_time(end)?)),
+            None => (parse_time(time)?, None),
+        };
+
+        if end == Some(start) {
+            anyhow::bail!("Event must end after it starts: {time}");
+        }
+
+        Ok((Some(Self { start, end }), rest.trim()))
+    }
+}
+
+impl fmt::Display for EventTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self { start, end } = self;
+
+        write!(f, "{}", start.format("%H:%M"))?;
+
+        if let Some(end) = end {
+            write!(f, "-{}", end.format("%H:%M"))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether the text is safe to use as one or more class names
+fn is_class_name(text: &str) -> bool {
+    !text.is_empty()
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == ' ')
+}
+
+/// Attributes of a single event, from a suffix such as `{style: font-weight:bold; icon: 🎂}`
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+struct EventAttributes {
+    // An inline style
+    style: Option<ArcStr>,
+    // Shown before the title
+    icon: Option<ArcStr>,
+    // Extra classes
+    class: Option<ArcStr>,
+    // Overrides the priority of the event's group
+    priority: Option<i32>,
+}
+
+impl EventAttributes {
+    /// Splits the attributes from the end of an event title
+    fn split_from_title(title_and_attributes: &str) -> anyhow::Result<(Self, &str)> {
+        let title_and_attributes = title_and_attributes.trim();
+
+        let Some((title, attributes)) = title_and_attributes
+            .strip_suffix('}')
+            .and_then(|title| title.rsplit_once('{'))
+        else {
+            return Ok((Self::default(), title_and_attributes));
+        };
+
+        let is_attribute = |attribute: &str| {
+            let key = attribute.split_once(':').map_or(attribute, |(key, _)| key);
+
+            ["style", "icon", "class", "priority"]
+                .into_iter()
+                .any(|name| key.trim().eq_ignore_ascii_case(name))
+        };
+
+        // Curly brackets which don't start with an attribute are part of the title, e.g. `Party {BYO}`
+        if !attributes
+            .split(';')
+            .map(str::trim)
+            .find(|attribute| !attribute.is_empty())
+            .is_some_and(is_attribute)
+        {
+            return Ok((Self::default(), title_and_attributes));
+        }
+
+        let mut style = Vec::new();
+        let mut icon = None;
+        let mut class = None;
+        let mut priority = None;
+
+        // Declarations after `style:` continue the style until the next attribute
+        let mut is_in_style = false;
+
+        for attribute in attributes
+            .split(';')
+            .map(str::trim)
+            .filter(|attribute| !attribute.is_empty())
+        {
+            let (key, value) = attribute
+                .split_once(':')
+                .map_or((attribute, ""), |(key, value)| (key.trim(), value.trim()));
+
+            let is_style = key.eq_ignore_ascii_case("style");
+            let is_icon = key.eq_ignore_ascii_case("icon");
+            let is_class = key.eq_ignore_ascii_case("class");
+            let is_priority = key.eq_ignore_ascii_case("priority");
+
+            if !(is_style || is_icon || is_class || is_priority) {
+                if !is_in_style {
+                    anyhow::bail!("Unknown event attribute {key}");
+                }
+
+                style.push(attribute);
+                continue;
+            }
+
+            if value.is_empty() {
+                anyhow::bail!("Event attribute {key} needs a value");
+            }
+
+            is_in_style = is_style;
+
+            if is_style {
+                style.push(value);
+            } else if is_icon {
+                icon = Some(value.into());
+            } else if is_priority {
+                priority = Some(
+                    value
+                        .parse()
+                        .with_context(|| format!("Invalid event priority {value}"))?,
+                );
+            } else {
+                if !is_class_name(value) {
+                    anyhow::bail!("Invalid event class {value}");
+                }
+
+                class = Some(value.into());
+            }
+        }
+
+        Ok((
+            Self {
+                style: (!style.is_empty()).then(|| style.join(";").into()),
+                icon,
+                class,
+                priority,
+            },
+            title.trim(),
+        ))
+    }
+}
+
+#[derive(serde::Serialize)]
 struct EventWithGroupId {
     title: ArcStr,
+    time: Option<EventTime>,
+    attributes: EventAttributes,
     group_id: GroupId,
+    // Events with a higher priority are shown first
+    priority: i32,
 }
 
 impl Default for EventWithGroupId {
     fn default() -> Self {
         Self {
             title: "".into(),
+            time: None,
+            attributes: EventAttributes::default(),
             group_id: GroupId::default(),
+            priority: 0,
+        }
+    }
+}
+
+impl EventWithGroupId {
+    /// Splits the events of a day into those which fit and those which don't,
+    /// keeping the events with the highest priority, then those of earlier groups.
+    /// When the events don't all fit, the last line is left for the number of hidden events
+    fn fit(events: Vec<Self>, max_events: usize) -> (Vec<Self>, Vec<Self>) {
+        if events.len() <= max_events {
+            return (events, Vec::new());
         }
+
+        let shown_count = max_events.saturating_sub(1);
+
+        let mut events = events.into_iter().enumerate().collect_vec();
+
+        events.sort_by_key(|(index, event)| (Reverse(event.priority), event.group_id, *index));
+
+        let mut hidden_events = events.split_off(shown_count);
+
+        // Back to the order they are shown in
+        events.sort_by_key(|&(index, _)| index);
+        hidden_events.sort_by_key(|&(index, _)| index);
+
+        (
+            events.into_iter().map(|(_, event)| event).collect(),
+            hidden_events.into_iter().map(|(_, event)| event).collect(),
+        )
+    }
+}
+
+/// The number of events which didn't fit in a day
+#[derive(Default, serde::Serialize)]
+struct MoreEvents(usize);
+
+impl MoreEvents {
+    fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl fmt::Display for MoreEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self(count) = self;
+
+        write!(f, "+{count} more")
+    }
+}
+
+/// The events of `day-wide` groups, which shade the whole day instead of adding a line
+#[derive(Default, serde::Serialize)]
+struct DayWideEvents(Vec<EventWithGroupId>);
+
+impl DayWideEvents {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The group classes, each after a space
+    fn classes(&self) -> String {
+        self.0
+            .iter()
+            .map(|event| event.group_id)
+            .dedup()
+            .map(|group_id| format!(" {group_id}"))
+            .collect()
+    }
+
+    fn titles(&self) -> String {
+        self.0.iter().map(|event| &event.title).join(", ")
     }
 }
 
+/// What is shown for a day in every kind of calendar
+#[derive(Default, serde::Serialize)]
+struct DayEvents {
+    events: Vec<EventWithGroupId>,
+    more_events: MoreEvents,
+    day_wide_events: DayWideEvents,
+    // The background class of days in `non-working` groups
+    non_working_class: Option<ArcStr>,
+}
+
+/// The events which didn't fit in a day, listed on a page at the end
+#[derive(serde::Serialize)]
+struct OverflowDay {
+    date: chrono::NaiveDate,
+    events: Vec<EventWithGroupId>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(transparent)]
 struct EventDay {
     day: u32,
 }
@@ -154,39 +464,111 @@ impl fmt::Display for EventDay {
     }
 }
 
+#[derive(serde::Serialize)]
+#[serde(tag = "type")]
 enum CalendarCell {
     Empty,
     Day {
         day: EventDay,
-        events: Vec<EventWithGroupId>,
+        #[serde(flatten)]
+        day_events: DayEvents,
     },
     MonthAndYear {
         month: Month,
         year: i32,
     },
+    // The previous or next month, in an otherwise empty cell
+    MiniMonth {
+        mini_month: MiniMonth,
+    },
 }
 
-struct CalendarEventStyles(Vec<(GroupId, ArcStr)>);
+/// The style of each group, which is also given to the days of `non-working` groups with their own class
+struct CalendarEventStyles(Vec<(GroupId, Option<ArcStr>, ArcStr)>);
 
 impl fmt::Display for CalendarEventStyles {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "<style>")?;
 
-        for (id, style) in &self.0 {
-            write!(f, ".{id} {{ {style} }}")?;
+        for (id, non_working_class, style) in &self.0 {
+            write!(f, ".{id}")?;
+
+            if let Some(non_working_class) = non_working_class {
+                write!(f, ", .{}", non_working_class.split_whitespace().join("."))?;
+            }
+
+            write!(f, " {{ {style} }}")?;
         }
 
         write!(f, "</style>")
     }
 }
 
-#[derive(Template)]
+#[derive(Template, serde::Serialize)]
 #[template(path = "monthly_calendar.html")]
 struct MonthlyCalendar {
     calendar_event_styles: CalendarEventStyles,
+    page_style: PageStyle,
+    theme_style: ThemeStyle,
     events: Vec<Vec<CalendarCell>>,
+    overflow: Vec<OverflowDay>,
+}
+
+impl MonthlyCalendar {
+    const MAX_EVENTS: usize = 4;
+    const CELLS_PER_MONTH: usize = 40;
+
+    /// Puts the previous month in the last empty cell before the month, and the next month in the last empty cell after it.
+    ///
+    /// If the month starts on a Monday, both go after it, as there's always space for two there.
+    fn add_mini_months(cells: &mut [CalendarCell], year: i32, month: Month) {
+        let is_empty = |cell: &CalendarCell| matches!(cell, CalendarCell::Empty);
+
+        let days_before_start = cells.iter().take_while(|cell| is_empty(cell)).count();
+
+        let mut empty_cells_after_end = cells
+            .iter()
+            .enumerate()
+            .skip(days_before_start)
+            .filter(|(_, cell)| is_empty(cell))
+            .map(|(index, _)| index)
+            .collect_vec()
+            .into_iter()
+            .rev();
+
+        let next_month = month.succ();
+        let next_year = if next_month == Month::January {
+            year + 1
+        } else {
+            year
+        };
+
+        if let Some(index) = empty_cells_after_end.next() {
+            cells[index] = CalendarCell::MiniMonth {
+                mini_month: MiniMonth::new(next_year, next_month),
+            };
+        }
+
+        let previous_month = month.pred();
+        let previous_year = if previous_month == Month::December {
+            year - 1
+        } else {
+            year
+        };
+
+        if let Some(index) = days_before_start
+            .checked_sub(1)
+            .or_else(|| empty_cells_after_end.next())
+        {
+            cells[index] = CalendarCell::MiniMonth {
+                mini_month: MiniMonth::new(previous_year, previous_month),
+            };
+        }
+    }
 }
 
+#[derive(serde::Serialize)]
+#[serde(tag = "type")]
 enum YearlyCalendarDay<W> {
     Empty {
         weekday: W,
@@ -194,7 +576,8 @@ enum YearlyCalendarDay<W> {
     Day {
         weekday: W,
         day: EventDay,
-        events: Vec<EventWithGroupId>,
+        #[serde(flatten)]
+        day_events: DayEvents,
     },
 }
 
@@ -205,53 +588,64 @@ impl YearlyCalendarDay<()> {
             Self::Day {
                 weekday: (),
                 day,
-                events,
+                day_events,
             } => YearlyCalendarDay::Day {
                 weekday,
                 day,
-                events,
+                day_events,
             },
         }
     }
 }
 
 impl YearlyCalendarDay<Weekday> {
-    fn background_class(&self) -> &'static str {
-        let is_weekend = match self {
-            YearlyCalendarDay::Empty { weekday } | YearlyCalendarDay::Day { weekday, .. } => {
-                weekday.is_weekend()
-            }
+    fn background_class(&self) -> &str {
+        let (weekday, non_working_class) = match self {
+            YearlyCalendarDay::Empty { weekday } => (weekday, None),
+            YearlyCalendarDay::Day {
+                weekday,
+                day_events,
+                ..
+            } => (weekday, day_events.non_working_class.as_deref()),
         };
 
-        if is_weekend {
-            "shadedBackground"
+        if let Some(non_working_class) = non_working_class {
+            non_working_class
+        } else if weekday.is_weekend() {
+            SHADED_BACKGROUND_CLASS
         } else {
             ""
         }
     }
 }
 
+#[derive(serde::Serialize)]
 struct YearlyCalendarMonth {
     month: Month,
     days: Vec<YearlyCalendarDay<Weekday>>,
 }
 
+#[derive(serde::Serialize)]
 struct YearlyCalendarPage {
     months: Vec<YearlyCalendarMonth>,
 }
 
-#[derive(Template)]
+#[derive(Template, serde::Serialize)]
 #[template(path = "yearly_calendar.html")]
 struct YearlyCalendar {
     title: &'static str,
     calendar_event_styles: CalendarEventStyles,
+    page_style: PageStyle,
+    theme_style: ThemeStyle,
     year: i32,
     weekday_titles: Vec<Weekday>,
     pages: Vec<YearlyCalendarPage>,
+    overflow: Vec<OverflowDay>,
 }
 
 impl YearlyCalendar {
     const ROWS_COUNT: usize = 37;
+    const MAX_EVENTS: usize = 2;
 
     fn body_class(&self) -> &'static str {
         if self.pages.len() == 1 {
@@ -262,1027 +656,6441 @@ impl YearlyCalendar {
     }
 }
 
+#[derive(serde::Serialize)]
+#[serde(tag = "type")]
 enum DiaryCell {
     Empty,
     Day {
         weekday: Weekday,
         day: EventDay,
-        events: Vec<EventWithGroupId>,
+        #[serde(flatten)]
+        day_events: DayEvents,
     },
 }
 
+impl DiaryCell {
+    fn background_class(&self) -> &str {
+        match self {
+            DiaryCell::Empty => "",
+            DiaryCell::Day {
+                day_events:
+                    DayEvents {
+                        non_working_class: Some(non_working_class),
+                        ..
+                    },
+                ..
+            } => non_working_class,
+            DiaryCell::Day { weekday, .. } if weekday.is_weekend() => SHADED_BACKGROUND_CLASS,
+            DiaryCell::Day { .. } => "",
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
 struct DiaryPage {
-    month: Month,
+    // None for the blank pages which pad out a booklet
+    month: Option<Month>,
     cells: Vec<DiaryCell>,
 }
 
-#[derive(Template)]
+#[derive(Template, serde::Serialize)]
 #[template(path = "diary.html")]
 struct Diary {
     calendar_event_styles: CalendarEventStyles,
+    page_style: PageStyle,
+    theme_style: ThemeStyle,
+    booklet: bool,
     pages: Vec<Vec<DiaryPage>>,
+    overflow: Vec<OverflowDay>,
 }
 
-#[derive(PartialEq, Eq, Hash)]
-struct MonthAndDay {
-    month: Month,
-    day: u32,
+impl Diary {
+    const MAX_EVENTS: usize = 2;
+    const PAGES_PER_SHEET_SIDE: usize = 8;
+    // Each side of a sheet holds two pages of the booklet, which are folded down the middle
+    const PAGES_PER_BOOKLET_PAGE: usize = Self::PAGES_PER_SHEET_SIDE / 2;
+
+    /// Reorders the pages so that printing them double-sided, then folding and stapling the stack of sheets,
+    /// gives a booklet with the pages in order.
+    ///
+    /// The booklet is padded with blank pages to a multiple of four booklet pages, as each sheet holds four.
+    fn impose_booklet(pages: Vec<DiaryPage>) -> Vec<DiaryPage> {
+        let mut booklet_pages = pages
+            .into_iter()
+            .chunks(Self::PAGES_PER_BOOKLET_PAGE)
+            .into_iter()
+            .map(Vec::from_iter)
+            .collect_vec();
+
+        while booklet_pages.len() % 4 != 0 {
+            booklet_pages.push(Vec::new());
+        }
+
+        for booklet_page in &mut booklet_pages {
+            booklet_page.resize_with(Self::PAGES_PER_BOOKLET_PAGE, || DiaryPage {
+                month: None,
+                cells: Vec::new(),
+            });
+        }
+
+        let count = booklet_pages.len();
+
+        let mut booklet_pages = booklet_pages.into_iter().map(Some).collect_vec();
+
+        // The front of each sheet has the last unplaced page on the left and the first on the right,
+        // and the back has the next page on the left and the one before the last on the right
+        (0..count / 2)
+            .step_by(2)
+            .flat_map(|index| [count - 1 - index, index, index + 1, count - 2 - index])
+            .flat_map(|index| booklet_pages[index].take().unwrap())
+            .collect()
+    }
 }
 
-#[derive(Clone, Data, PartialEq, Eq)]
-enum Output {
-    MonthlyCalendar,
-    YearlyCalendar { split_in_two: bool },
-    Diary,
+#[derive(serde::Serialize)]
+struct WeeklyPlannerDay {
+    date: chrono::NaiveDate,
+    #[serde(flatten)]
+    day_events: DayEvents,
 }
 
-#[derive(Clone)]
-enum EventDescriptionData {
-    FixedDate {
-        month: Month,
-        day: u32,
-    },
-    NthWeekdayOfMonth {
-        n: i16,
-        weekday: Weekday,
-        // None Means Every Month
-        month: Option<Month>,
-    },
-    DaysAfterEaster {
-        day_offset: i16,
-    },
-    FuzzySunday(Box<EventDescriptionData>),
-    WeekdayBefore {
-        weekday: Weekday,
-        base: Box<EventDescriptionData>,
-    },
+impl WeeklyPlannerDay {
+    fn background_class(&self) -> &str {
+        if let Some(non_working_class) = &self.day_events.non_working_class {
+            non_working_class
+        } else if self.date.weekday().is_weekend() {
+            SHADED_BACKGROUND_CLASS
+        } else {
+            ""
+        }
+    }
 }
 
-impl EventDescriptionData {
-    fn dates(&self, year: i32) -> anyhow::Result<Vec<chrono::NaiveDate>> {
-        match *self {
-            EventDescriptionData::FixedDate { month, day } => {
-                Ok(vec![chrono::NaiveDate::from_ymd_opt(
-                    year,
-                    month.number_from_month(),
-                    day,
-                )
-                .with_context(|| {
-                    format!("Invalid date {year}/{}/{day}", month.name())
-                })?])
-            }
-            EventDescriptionData::NthWeekdayOfMonth { n, weekday, month } => month
-                .as_ref()
-                .map_or(&MONTHS[..], std::slice::from_ref)
-                .iter()
-                .filter_map(|&month| {
-                    fn nth_weekday(
-                        year: i32,
-                        month: Month,
-                        weekday: Weekday,
-                        n: i16,
-                    ) -> anyhow::Result<Option<chrono::NaiveDate>> {
-                        Ok(match n.cmp(&0) {
-                            std::cmp::Ordering::Equal => {
-                                anyhow::bail!("nth weekday cannot be 0");
-                            }
-                            std::cmp::Ordering::Greater => {
-                                let first = find_date(year, month, 1, weekday, 1);
+/// A week from Monday to Sunday, which can start or end in a neighbouring year
+#[derive(serde::Serialize)]
+struct WeeklyPlannerWeek {
+    days: Vec<WeeklyPlannerDay>,
+}
 
-                                let event_day = first + chrono::Duration::weeks((n - 1).into());
+impl WeeklyPlannerWeek {
+    fn iso_week(&self) -> chrono::IsoWeek {
+        self.days[0].date.iso_week()
+    }
 
-                                (event_day.with_day0(first.day0()) == Some(first))
-                                    .then_some(event_day)
-                            }
-                            std::cmp::Ordering::Less => {
-                                let last =
-                                    find_date(year, month, days_in_month(year, month), weekday, -1);
+    /// The months of the week, e.g. "April 2024", "April – May 2024" or "December 2024 – January 2025"
+    fn months(&self) -> String {
+        let first_day = self.days[0].date;
+        let last_day = self.days[self.days.len() - 1].date;
+
+        if first_day.year() != last_day.year() {
+            format!(
+                "{} – {}",
+                first_day.format("%B %Y"),
+                last_day.format("%B %Y")
+            )
+        } else if first_day.month() != last_day.month() {
+            format!("{} – {}", first_day.format("%B"), last_day.format("%B %Y"))
+        } else {
+            first_day.format("%B %Y").to_string()
+        }
+    }
+}
 
-                                let event_day = last + chrono::Duration::weeks((n + 1).into());
+#[derive(Template, serde::Serialize)]
+#[template(path = "weekly_planner.html")]
+struct WeeklyPlanner {
+    calendar_event_styles: CalendarEventStyles,
+    page_style: PageStyle,
+    theme_style: ThemeStyle,
+    weeks: Vec<WeeklyPlannerWeek>,
+    overflow: Vec<OverflowDay>,
+}
 
-                                (event_day.with_day0(last.day0()) == Some(last))
-                                    .then_some(event_day)
-                            }
-                        })
-                    }
+impl WeeklyPlanner {
+    const MAX_EVENTS: usize = 8;
+}
 
-                    nth_weekday(year, month, weekday, n).transpose()
-                })
-                .collect(),
-            EventDescriptionData::DaysAfterEaster { day_offset } => {
-                let easter = computus::gregorian(year)
-                    .map_err(|err| anyhow::anyhow!("Failed to calculate Easter: {err}"))
-                    .map(|computus::Date { year, month, day }| {
-                        chrono::NaiveDate::from_ymd_opt(year, month, day).unwrap()
-                    })?;
+/// A small calendar of a month, with weeks from Monday to Sunday
+#[derive(serde::Serialize)]
+struct MiniMonth {
+    month: Month,
+    year: i32,
+    weeks: Vec<Vec<Option<u32>>>,
+    highlighted_day: Option<u32>,
+}
 
-                Ok(vec![easter + chrono::Duration::days(day_offset.into())])
-            }
-            EventDescriptionData::FuzzySunday(ref event_description_data) => event_description_data
-                .dates(year)?
-                .into_iter()
-                .map(|date| match date.weekday() {
-                    Weekday::Mon => date
-                        .checked_sub_days(chrono::Days::new(1))
-                        .with_context(|| format!("No date before {date}")),
-                    Weekday::Sat => date
-                        .checked_add_days(chrono::Days::new(1))
-                        .with_context(|| format!("No date after {date}")),
-                    _ => Ok(date),
-                })
-                .collect(),
-            EventDescriptionData::WeekdayBefore { weekday, ref base } => base
-                .dates(year)?
-                .into_iter()
-                .map(|date| {
-                    let offset = if weekday == date.weekday() {
-                        7
-                    } else {
-                        date.weekday().days_since(weekday)
-                    };
+impl MiniMonth {
+    fn new(year: i32, month: Month) -> Self {
+        let days_before_start = chrono::NaiveDate::from_ymd_opt(year, month.number_from_month(), 1)
+            .unwrap()
+            .weekday()
+            .num_days_from_monday() as usize;
+
+        let days = std::iter::repeat_n(None, days_before_start)
+            .chain((1..=days_in_month(year, month)).map(Some))
+            .collect_vec();
 
-                    date.checked_sub_days(chrono::Days::new(offset.into()))
-                        .with_context(|| format!("Cannot calculate {weekday} before {date}"))
+        Self {
+            month,
+            year,
+            weeks: days
+                .chunks(7)
+                .map(|week| {
+                    let mut week = week.to_vec();
+                    week.resize(7, None);
+                    week
                 })
                 .collect(),
+            highlighted_day: None,
         }
     }
-}
 
-trait StrExt {
-    fn case_insensitive_strip_prefix<'a>(&'a self, prefix: &str) -> Option<&'a Self>;
+    fn is_highlighted(&self, day: &Option<u32>) -> bool {
+        day.is_some() && *day == self.highlighted_day
+    }
+}
 
-    fn first_n_characters(&self, n: usize) -> String;
+/// The writing space of each page of a journal
+#[derive(Clone, Copy, Data, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+enum JournalPaper {
+    Ruled,
+    DotGrid,
+    Hourly,
 }
 
-impl StrExt for str {
-    fn case_insensitive_strip_prefix<'a>(&'a self, prefix: &str) -> Option<&'a Self> {
-        let mut chars = self.chars();
+impl JournalPaper {
+    fn class(self) -> &'static str {
+        match self {
+            Self::Ruled => "ruled",
+            Self::DotGrid => "dotGrid",
+            Self::Hourly => "hourly",
+        }
+    }
+}
 
-        prefix
-            .chars()
-            .zip(&mut chars)
-            .all(|(a, b)| char::eq_ignore_ascii_case(&a, &b))
-            .then_some(chars.as_str())
+/// An hour of an hourly journal page, with the events starting in that hour
+#[derive(serde::Serialize)]
+struct JournalSlot {
+    hour: u32,
+    events: Vec<EventWithGroupId>,
+}
+
+#[derive(serde::Serialize)]
+struct JournalPage {
+    date: chrono::NaiveDate,
+    #[serde(flatten)]
+    day_events: DayEvents,
+    slots: Vec<JournalSlot>,
+    mini_month: MiniMonth,
+}
+
+#[derive(Template, serde::Serialize)]
+#[template(path = "daily_journal.html")]
+struct DailyJournal {
+    calendar_event_styles: CalendarEventStyles,
+    page_style: PageStyle,
+    theme_style: ThemeStyle,
+    paper: JournalPaper,
+    pages: Vec<JournalPage>,
+    overflow: Vec<OverflowDay>,
+}
+
+impl DailyJournal {
+    const MAX_EVENTS: usize = 10;
+    const FIRST_HOUR: u32 = 7;
+    const LAST_HOUR: u32 = 21;
+}
+
+#[derive(Debug, Clone, Copy, Data, PartialEq, Eq, clap::ValueEnum)]
+enum PaperSize {
+    A3,
+    A4,
+    A5,
+    Letter,
+    Legal,
+}
+
+impl PaperSize {
+    const ALL: [Self; 5] = [Self::A3, Self::A4, Self::A5, Self::Letter, Self::Legal];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::A3 => "A3",
+            Self::A4 => "A4",
+            Self::A5 => "A5",
+            Self::Letter => "Letter",
+            Self::Legal => "Legal",
+        }
     }
 
-    fn first_n_characters(&self, n: usize) -> String {
-        self.chars().take(n).collect()
+    /// The width and height in portrait, in millimetres
+    fn millimetres(self) -> (f64, f64) {
+        match self {
+            Self::A3 => (297.0, 420.0),
+            Self::A4 => (210.0, 297.0),
+            Self::A5 => (148.0, 210.0),
+            Self::Letter => (215.9, 279.4),
+            Self::Legal => (215.9, 355.6),
+        }
     }
 }
 
-#[derive(Clone)]
-struct EventDescription {
-    title: ArcStr,
-    data: EventDescriptionData,
-    group_id: GroupId,
+#[derive(Debug, Clone, Copy, Data, PartialEq, Eq, clap::ValueEnum)]
+enum Orientation {
+    /// Whichever suits the calendar type
+    Auto,
+    Portrait,
+    Landscape,
 }
 
-impl EventDescription {
-    fn parse(input: &str, group_id: GroupId) -> anyhow::Result<Self> {
-        if let Some(input) = input.case_insensitive_strip_prefix("ho repl ") {
-            let Self {
-                title,
-                data,
-                group_id,
-            } = Self::parse(input, group_id)?;
+/// The paper the calendar is printed on, which is given to the browser as an `@page` rule
+#[derive(Clone, Copy, Data, Lens, PartialEq)]
+struct PageSetup {
+    paper_size: PaperSize,
+    orientation: Orientation,
+    // In millimetres
+    margin: f64,
+    // In millimetres, printed beyond the edge of the page to be trimmed off
+    bleed: f64,
+}
 
-            return Ok(Self {
-                title,
-                data: EventDescriptionData::FuzzySunday(Box::new(data)),
-                group_id,
-            });
+impl PageSetup {
+    const DEFAULT: Self = Self {
+        paper_size: PaperSize::A4,
+        orientation: Orientation::Auto,
+        margin: 10.0,
+        bleed: 0.0,
+    };
+
+    const MARGIN_RANGE: std::ops::RangeInclusive<f64> = 0.0..=50.0;
+    const BLEED_RANGE: std::ops::RangeInclusive<f64> = 0.0..=10.0;
+
+    /// Parses a margin or bleed argument, which must be in the same range as in the Page Setup box
+    fn parse_millimetres(text: &str, range: std::ops::RangeInclusive<f64>) -> Result<f64, String> {
+        let millimetres = text.parse::<f64>().map_err(|err| err.to_string())?;
+
+        if !range.contains(&millimetres) {
+            return Err(format!(
+                "must be from {} to {} millimetres",
+                range.start(),
+                range.end()
+            ));
         }
 
-        if let Some((weekday, input)) = Weekday::ALL.iter().find_map(|&weekday| {
-            Some((
-                weekday,
-                input
-                    .case_insensitive_strip_prefix(weekday.name())?
-                    .case_insensitive_strip_prefix(" before ")?,
-            ))
-        }) {
-            let Self {
-                title,
-                data,
-                group_id,
-            } = Self::parse(input, group_id)?;
+        Ok(millimetres)
+    }
 
-            return Ok(Self {
-                title,
-                data: EventDescriptionData::WeekdayBefore {
-                    weekday,
-                    base: Box::new(data),
-                },
-                group_id,
-            });
+    fn page_style(&self, output: &Output) -> PageStyle {
+        let is_landscape = match self.orientation {
+            Orientation::Auto => output.is_landscape(),
+            Orientation::Portrait => false,
+            Orientation::Landscape => true,
+        };
+
+        PageStyle {
+            page_setup: *self,
+            is_landscape,
         }
+    }
+}
 
-        let Some((index, category, title)) = Some(input).and_then(|input| {
-            let space_or_tab = |c: char| c == ' ' || c == '\t';
+impl Default for PageSetup {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
 
-            let (index, input) = input.trim().split_once(space_or_tab)?;
-            let (month_or_weekday, title) = input.trim().split_once(space_or_tab)?;
+/// The most events shown in a day of each calendar type, including the line for how many more there are
+#[derive(Clone, Copy, Data, PartialEq, Eq)]
+struct MaxEvents {
+    monthly_calendar: usize,
+    yearly_calendar: usize,
+    diary: usize,
+    weekly_planner: usize,
+    daily_journal: usize,
+}
 
-            Some((index.trim(), month_or_weekday.trim(), title.trim()))
-        }) else {
-            anyhow::bail!("Invalid event: {input}")
-        };
+impl MaxEvents {
+    const DEFAULT: Self = Self {
+        monthly_calendar: MonthlyCalendar::MAX_EVENTS,
+        yearly_calendar: YearlyCalendar::MAX_EVENTS,
+        diary: Diary::MAX_EVENTS,
+        weekly_planner: WeeklyPlanner::MAX_EVENTS,
+        daily_journal: DailyJournal::MAX_EVENTS,
+    };
 
-        let index = index
-            .parse::<i16>()
-            .with_context(|| format!("Invalid index {index}"))?;
+    const RANGE: std::ops::RangeInclusive<usize> = 1..=20;
 
-        Ok(Self {
-            title: title.into(),
-            group_id,
-            data: if category.eq_ignore_ascii_case("easter") {
-                EventDescriptionData::DaysAfterEaster { day_offset: index }
-            } else if let Some((weekday, month)) =
-                category.split_once('/').and_then(|(weekday, month)| {
-                    Some((
-                        Weekday::from_str(weekday).ok()?,
-                        Month::from_str(month).ok()?,
-                    ))
-                })
-            {
-                EventDescriptionData::NthWeekdayOfMonth {
-                    n: index,
-                    weekday,
-                    month: Some(month),
-                }
-            } else if let Ok(month) = Month::from_str(category) {
-                EventDescriptionData::FixedDate {
-                    month,
-                    day: index
-                        .try_into()
-                        .map_err(|_| anyhow::anyhow!("Invalid date {}/{index}", month.name()))?,
-                }
-            } else if let Ok(weekday) = Weekday::from_str(category) {
-                EventDescriptionData::NthWeekdayOfMonth {
-                    n: index,
-                    weekday,
-                    month: None,
-                }
+    fn get_mut(&mut self, output: &Output) -> &mut usize {
+        match output {
+            Output::MonthlyCalendar => &mut self.monthly_calendar,
+            Output::YearlyCalendar { .. } => &mut self.yearly_calendar,
+            Output::Diary { .. } => &mut self.diary,
+            Output::WeeklyPlanner => &mut self.weekly_planner,
+            Output::DailyJournal { .. } => &mut self.daily_journal,
+        }
+    }
+
+    fn get(mut self, output: &Output) -> usize {
+        *self.get_mut(output)
+    }
+}
+
+impl Default for MaxEvents {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+struct PageStyle {
+    page_setup: PageSetup,
+    is_landscape: bool,
+}
+
+impl fmt::Display for PageStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            page_setup:
+                PageSetup {
+                    paper_size,
+                    orientation: _,
+                    margin,
+                    bleed,
+                },
+            is_landscape,
+        } = self;
+
+        let (mut width, mut height) = paper_size.millimetres();
+
+        if *is_landscape {
+            (width, height) = (height, width);
+        }
+
+        // The size of the page inside the margins, for the templates to size each page to when printing,
+        // as the viewport isn't the page then. Rounded to a tenth of a millimetre like the paper sizes
+        let inside_margins = |length: f64| ((length - 2.0 * margin) * 10.0).round() / 10.0;
+
+        write!(
+            f,
+            "<style>@page {{ size: {} {}; margin: {margin}mm; bleed: {bleed}mm; }} \
+            @media print {{ :root {{ --page-width: {}mm; --page-height: {}mm; }} }}</style>",
+            paper_size.name(),
+            if *is_landscape {
+                "landscape"
             } else {
-                anyhow::bail!("Invalid event: {input}")
+                "portrait"
             },
-        })
+            inside_margins(width),
+            inside_margins(height),
+        )
     }
 }
 
-#[derive(Clone, Data, Lens)]
-struct EventGroupDescription {
-    #[data(ignore)]
-    id: GroupId,
-    #[data(ignore)]
-    title: ArcStr,
-    #[data(ignore)]
-    style: Option<ArcStr>,
-    #[data(ignore)]
-    events: Vector<EventDescription>,
-    is_selected: bool,
+/// A look for the calendars, from a TOML file of CSS values, e.g. `weekend-background = "#e8e8e8"`.
+///
+/// Each value is given to the templates as a CSS variable, e.g. `--weekend-background`, which replaces their own value
+#[derive(Clone, Data)]
+struct Theme {
+    name: ArcStr,
+    variables: Vector<(ArcStr, ArcStr)>,
 }
 
-#[derive(Clone)]
-struct ErrorMessage(ArcStr);
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            name: "Default".into(),
+            variables: Vector::new(),
+        }
+    }
+}
 
-impl ErrorMessage {
-    fn new(err: anyhow::Error) -> Self {
-        Self(format!("{err:?}").into())
+impl Theme {
+    fn parse(name: &str, text: &str) -> anyhow::Result<Self> {
+        let table = text
+            .parse::<toml::Table>()
+            .with_context(|| format!("Failed to parse theme {name}"))?;
+
+        let variables = table
+            .into_iter()
+            .map(|(key, value)| {
+                if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                    anyhow::bail!("Invalid variable {key} in theme {name}");
+                }
+
+                let Some(value) = value.as_str() else {
+                    anyhow::bail!("The value of {key} in theme {name} must be a string");
+                };
+
+                if value.contains([';', '{', '}', '<', '>']) {
+                    anyhow::bail!("Invalid value of {key} in theme {name}: {value}");
+                }
+
+                Ok((key.into(), value.into()))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            name: name.into(),
+            variables,
+        })
+    }
+
+    /// The default theme, the bundled themes, then the `.toml` files in the themes directory if it exists
+    fn load_all(themes_directory: Option<&std::path::Path>) -> anyhow::Result<Vector<Self>> {
+        let mut themes = std::iter::once(Ok(Self::default()))
+            .chain(THEMES.iter().map(|(name, text)| Self::parse(name, text)))
+            .collect::<anyhow::Result<Vector<_>>>()?;
+
+        let Some(entries) =
+            themes_directory.and_then(|themes_directory| std::fs::read_dir(themes_directory).ok())
+        else {
+            return Ok(themes);
+        };
+
+        let mut paths = entries
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to read themes directory")?;
+
+        paths.retain(|path| path.extension() == Some("toml".as_ref()));
+        paths.sort();
+
+        for path in paths {
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+
+            themes.push_back(Self::parse(
+                &path.file_stem().unwrap_or_default().to_string_lossy(),
+                &text,
+            )?);
+        }
+
+        Ok(themes)
     }
 }
 
-impl Data for ErrorMessage {
-    fn same(&self, other: &Self) -> bool {
-        std::sync::Arc::ptr_eq(&self.0, &other.0)
+/// The variables of the selected theme, as a style for the templates
+struct ThemeStyle(Vector<(ArcStr, ArcStr)>);
+
+impl fmt::Display for ThemeStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+
+        write!(f, "<style>:root {{")?;
+
+        for (key, value) in &self.0 {
+            write!(f, " --{key}: {value};")?;
+        }
+
+        write!(f, " }}</style>")
     }
 }
 
-impl druid::piet::TextStorage for ErrorMessage {
-    fn as_str(&self) -> &str {
-        self.0.as_str()
+#[derive(Clone, Data, PartialEq, Eq)]
+enum Output {
+    MonthlyCalendar,
+    YearlyCalendar { split_in_two: bool },
+    Diary { booklet: bool },
+    WeeklyPlanner,
+    DailyJournal { paper: JournalPaper },
+}
+
+impl Output {
+    fn is_landscape(&self) -> bool {
+        !matches!(self, Self::WeeklyPlanner | Self::DailyJournal { .. })
     }
 }
 
-impl druid::text::TextStorage for ErrorMessage {}
+/// Serializes values for user templates as the text they are shown as in the built-in templates
+macro_rules! serialize_as_display {
+    ($($t:ty),* $(,)?) => {
+        $(
+            impl serde::Serialize for $t {
+                fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    serializer.collect_str(self)
+                }
+            }
+        )*
+    };
+}
 
-#[derive(Clone, Data, Lens)]
-struct AppState {
-    error_message: Option<ErrorMessage>,
-    year: i32,
-    output: Output,
-    event_group_descriptions: Vector<EventGroupDescription>,
+serialize_as_display!(
+    GroupId,
+    EventTime,
+    CalendarEventStyles,
+    PageStyle,
+    ThemeStyle
+);
+
+/// A built-in template, which is replaced by a file of the same name in the user's templates directory.
+///
+/// User templates are rendered by [minijinja](https://docs.rs/minijinja), with the same data as the built-in templates
+trait UserTemplate: Template + serde::Serialize {
+    const FILE_NAME: &'static str;
+
+    fn render_with_user_template(
+        &self,
+        templates_directory: Option<&std::path::Path>,
+    ) -> anyhow::Result<String> {
+        let Some(templates_directory) = templates_directory
+            .filter(|templates_directory| templates_directory.join(Self::FILE_NAME).is_file())
+        else {
+            return self.render().context("Failed to render calendar");
+        };
+
+        let mut environment = minijinja::Environment::new();
+        environment.set_loader(minijinja::path_loader(templates_directory));
+
+        let output = environment
+            .get_template(Self::FILE_NAME)
+            .and_then(|template| template.render(self))
+            .with_context(|| {
+                format!(
+                    "Failed to render {}",
+                    templates_directory.join(Self::FILE_NAME).display()
+                )
+            })?;
+
+        Ok(format!("{HTTP_RESPONSE_HEADER}{output}"))
+    }
 }
 
-impl AppState {
-    fn show_calendar(&self, events: druid::ExtEventSink) -> anyhow::Result<()> {
-        let year = self.year;
+impl UserTemplate for MonthlyCalendar {
+    const FILE_NAME: &'static str = "monthly_calendar.html";
+}
 
-        let mut calendar_events = HashMap::new();
+impl UserTemplate for YearlyCalendar {
+    const FILE_NAME: &'static str = "yearly_calendar.html";
+}
 
-        for EventGroupDescription {
-            events,
-            is_selected,
-            ..
-        } in &self.event_group_descriptions
-        {
-            if !is_selected {
-                continue;
+impl UserTemplate for Diary {
+    const FILE_NAME: &'static str = "diary.html";
+}
+
+impl UserTemplate for WeeklyPlanner {
+    const FILE_NAME: &'static str = "weekly_planner.html";
+}
+
+impl UserTemplate for DailyJournal {
+    const FILE_NAME: &'static str = "daily_journal.html";
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DateRelation {
+    Before,
+    OnOrBefore,
+    After,
+    OnOrAfter,
+}
+
+impl DateRelation {
+    // Longest first, so that "on or before" isn't mistaken for "before"
+    const ALL: [Self; 4] = [Self::OnOrBefore, Self::OnOrAfter, Self::Before, Self::After];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Before => "before",
+            Self::OnOrBefore => "on or before",
+            Self::After => "after",
+            Self::OnOrAfter => "on or after",
+        }
+    }
+
+    fn nth_weekday(
+        self,
+        date: chrono::NaiveDate,
+        weekday: Weekday,
+        n: u16,
+    ) -> anyhow::Result<chrono::NaiveDate> {
+        if n == 0 {
+            anyhow::bail!("nth weekday cannot be 0");
+        }
+
+        let is_before = matches!(self, Self::Before | Self::OnOrBefore);
+        let is_inclusive = matches!(self, Self::OnOrBefore | Self::OnOrAfter);
+
+        let offset = if is_before {
+            date.weekday().days_since(weekday)
+        } else {
+            weekday.days_since(date.weekday())
+        };
+
+        let offset = if offset == 0 && !is_inclusive {
+            7
+        } else {
+            offset
+        };
+
+        let days = chrono::Days::new(u64::from(offset) + 7 * (u64::from(n) - 1));
+
+        if is_before {
+            date.checked_sub_days(days)
+        } else {
+            date.checked_add_days(days)
+        }
+        .with_context(|| format!("Cannot calculate {n} {weekday} {} {date}", self.name()))
+    }
+
+    fn nth_workday(
+        self,
+        date: chrono::NaiveDate,
+        n: u16,
+        non_working_days: &NonWorkingDays,
+    ) -> anyhow::Result<chrono::NaiveDate> {
+        if n == 0 {
+            anyhow::bail!("nth workday cannot be 0");
+        }
+
+        let step = match self {
+            Self::Before | Self::OnOrBefore => chrono::NaiveDate::pred_opt,
+            Self::After | Self::OnOrAfter => chrono::NaiveDate::succ_opt,
+        };
+
+        let error = || format!("Cannot calculate {n} workdays {} {date}", self.name());
+
+        let mut candidate = match self {
+            Self::OnOrBefore | Self::OnOrAfter => date,
+            Self::Before | Self::After => step(&date).with_context(error)?,
+        };
+
+        let mut remaining = n;
+
+        loop {
+            if non_working_days.is_workday(candidate) {
+                remaining -= 1;
+
+                if remaining == 0 {
+                    return Ok(candidate);
+                }
             }
 
-            for &EventDescription {
-                ref title,
-                ref data,
-                group_id,
-            } in events
-            {
-                for date in data.dates(year)? {
-                    let month =
-                        Month::try_from((date.month()) as u8).context("Failed to get month")?;
-                    let day = date.day();
+            candidate = step(&candidate).with_context(error)?;
+        }
+    }
+}
 
-                    calendar_events
-                        .entry(MonthAndDay { month, day })
-                        .or_insert_with(Vec::new)
-                        .push(EventWithGroupId {
-                            title: title.clone(),
-                            group_id,
-                        });
+/// The dates of events in groups marked as `non-working`, which are skipped when counting workdays
+#[derive(Default)]
+struct NonWorkingDays(HashSet<chrono::NaiveDate>);
+
+impl NonWorkingDays {
+    fn new(
+        event_group_descriptions: &Vector<EventGroupDescription>,
+        year: i32,
+    ) -> anyhow::Result<Self> {
+        let mut non_working_days = HashSet::new();
+
+        // Workday offsets can cross into the neighbouring years.
+        // Workday rules within non-working groups only skip weekends
+        for event_group_description in event_group_descriptions {
+            if !event_group_description.is_non_working || !event_group_description.is_selected {
+                continue;
+            }
+
+            for year in year - 1..=year + 1 {
+                for (_, dates) in event_group_description.event_dates(year, &Self::default())? {
+                    non_working_days.extend(dates);
                 }
             }
         }
 
-        let calendar_event_styles = CalendarEventStyles(
+        Ok(Self(non_working_days))
+    }
+
+    fn is_workday(&self, date: chrono::NaiveDate) -> bool {
+        !date.weekday().is_weekend() && !self.0.contains(&date)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum EventDescriptionData {
+    FixedDate {
+        month: Month,
+        day: u32,
+    },
+    NthWeekdayOfMonth {
+        n: i16,
+        weekday: Weekday,
+        // None Means Every Month
+        month: Option<Month>,
+    },
+    DaysAfterEaster {
+        day_offset: i16,
+    },
+    FuzzySunday(Box<EventDescriptionData>),
+    NthWeekdayRelativeTo {
+        n: u16,
+        weekday: Weekday,
+        relation: DateRelation,
+        base: Box<EventDescriptionData>,
+    },
+    // Weekends move to the following Monday
+    NextWeekday(Box<EventDescriptionData>),
+    // Saturdays move to the Friday before, Sundays to the Monday after
+    NearestWeekday(Box<EventDescriptionData>),
+    // As NextWeekday, but also skipping the other events in the group. See EventGroupDescription::event_dates
+    Substitute(Box<EventDescriptionData>),
+    NthWorkdayOfMonth {
+        n: i16,
+        // None Means Every Month
+        month: Option<Month>,
+    },
+    NthWorkdayRelativeTo {
+        n: u16,
+        relation: DateRelation,
+        base: Box<EventDescriptionData>,
+    },
+    DayOfMonth {
+        n: i16,
+        // None Means Every Month
+        month: Option<Month>,
+    },
+    NthWeekdayOfYear {
+        n: i16,
+        weekday: Weekday,
+    },
+    HebrewDate {
+        month: HebrewMonth,
+        day: u8,
+    },
+    HijriDate {
+        // 1 is Muharram
+        month: u8,
+        day: u8,
+    },
+    ChineseDate {
+        month: u8,
+        day: u8,
+    },
+    DaysAfter {
+        day_offset: i16,
+        base: Box<EventDescriptionData>,
+    },
+    // The weekday of an ISO 8601 week, which may fall in the previous or next calendar year
+    WeekdayOfIsoWeek {
+        week: i16,
+        weekday: Weekday,
+    },
+}
+
+impl EventDescriptionData {
+    fn dates(
+        &self,
+        year: i32,
+        non_working_days: &NonWorkingDays,
+    ) -> anyhow::Result<Vec<chrono::NaiveDate>> {
+        match *self {
+            // 29 February only occurs in leap years
+            EventDescriptionData::FixedDate { month, day } => {
+                Ok(
+                    chrono::NaiveDate::from_ymd_opt(year, month.number_from_month(), day)
+                        .into_iter()
+                        .collect(),
+                )
+            }
+            EventDescriptionData::NthWeekdayOfMonth { n, weekday, month } => month
+                .as_ref()
+                .map_or(&MONTHS[..], std::slice::from_ref)
+                .iter()
+                .filter_map(|&month| {
+                    fn nth_weekday(
+                        year: i32,
+                        month: Month,
+                        weekday: Weekday,
+                        n: i16,
+                    ) -> anyhow::Result<Option<chrono::NaiveDate>> {
+                        Ok(match n.cmp(&0) {
+                            std::cmp::Ordering::Equal => {
+                                anyhow::bail!("nth weekday cannot be 0");
+                            }
+                            std::cmp::Ordering::Greater => {
+                                let first = find_date(year, month, 1, weekday, 1);
+
+                                let event_day = first + chrono::Duration::weeks((n - 1).into());
+
+                                (event_day.with_day0(first.day0()) == Some(first))
+                                    .then_some(event_day)
+                            }
+                            std::cmp::Ordering::Less => {
+                                let last =
+                                    find_date(year, month, days_in_month(year, month), weekday, -1);
+
+                                let event_day = last + chrono::Duration::weeks((n + 1).into());
+
+                                (event_day.with_day0(last.day0()) == Some(last))
+                                    .then_some(event_day)
+                            }
+                        })
+                    }
+
+                    nth_weekday(year, month, weekday, n).transpose()
+                })
+                .collect(),
+            EventDescriptionData::DaysAfterEaster { day_offset } => {
+                let easter = computus::gregorian(year)
+                    .map_err(|err| anyhow::anyhow!("Failed to calculate Easter: {err}"))
+                    .map(|computus::Date { year, month, day }| {
+                        chrono::NaiveDate::from_ymd_opt(year, month, day).unwrap()
+                    })?;
+
+                Ok(vec![easter + chrono::Duration::days(day_offset.into())])
+            }
+            EventDescriptionData::FuzzySunday(ref event_description_data) => event_description_data
+                .dates(year, non_working_days)?
+                .into_iter()
+                .map(|date| match date.weekday() {
+                    Weekday::Mon => date
+                        .checked_sub_days(chrono::Days::new(1))
+                        .with_context(|| format!("No date before {date}")),
+                    Weekday::Sat => date
+                        .checked_add_days(chrono::Days::new(1))
+                        .with_context(|| format!("No date after {date}")),
+                    _ => Ok(date),
+                })
+                .collect(),
+            EventDescriptionData::NthWeekdayRelativeTo {
+                n,
+                weekday,
+                relation,
+                ref base,
+            } => base
+                .dates(year, non_working_days)?
+                .into_iter()
+                .map(|date| relation.nth_weekday(date, weekday, n))
+                .collect(),
+            EventDescriptionData::NextWeekday(ref event_description_data)
+            | EventDescriptionData::Substitute(ref event_description_data) => {
+                event_description_data
+                    .dates(year, non_working_days)?
+                    .into_iter()
+                    .map(next_weekday)
+                    .collect()
+            }
+            EventDescriptionData::NearestWeekday(ref event_description_data) => {
+                event_description_data
+                    .dates(year, non_working_days)?
+                    .into_iter()
+                    .map(|date| match date.weekday() {
+                        Weekday::Sat => date
+                            .checked_sub_days(chrono::Days::new(1))
+                            .with_context(|| format!("No date before {date}")),
+                        Weekday::Sun => date
+                            .checked_add_days(chrono::Days::new(1))
+                            .with_context(|| format!("No date after {date}")),
+                        _ => Ok(date),
+                    })
+                    .collect()
+            }
+            EventDescriptionData::NthWorkdayOfMonth { n, month } => month
+                .as_ref()
+                .map_or(&MONTHS[..], std::slice::from_ref)
+                .iter()
+                .filter_map(|&month| {
+                    let workdays = (1..=days_in_month(year, month))
+                        .filter_map(|day| {
+                            chrono::NaiveDate::from_ymd_opt(year, month.number_from_month(), day)
+                        })
+                        .filter(|&date| non_working_days.is_workday(date))
+                        .collect_vec();
+
+                    let index = match n.cmp(&0) {
+                        std::cmp::Ordering::Equal => {
+                            return Some(Err(anyhow::anyhow!("nth workday cannot be 0")));
+                        }
+                        std::cmp::Ordering::Greater => usize::from(n.unsigned_abs()) - 1,
+                        std::cmp::Ordering::Less => {
+                            workdays.len().checked_sub(n.unsigned_abs().into())?
+                        }
+                    };
+
+                    workdays.get(index).copied().map(Ok)
+                })
+                .collect(),
+            EventDescriptionData::NthWorkdayRelativeTo {
+                n,
+                relation,
+                ref base,
+            } => base
+                .dates(year, non_working_days)?
+                .into_iter()
+                .map(|date| relation.nth_workday(date, n, non_working_days))
+                .collect(),
+            EventDescriptionData::DayOfMonth { n, month } => month
+                .as_ref()
+                .map_or(&MONTHS[..], std::slice::from_ref)
+                .iter()
+                .filter_map(|&month| {
+                    let day = match n.cmp(&0) {
+                        std::cmp::Ordering::Equal => {
+                            return Some(Err(anyhow::anyhow!("day of month cannot be 0")));
+                        }
+                        std::cmp::Ordering::Greater => u32::from(n.unsigned_abs()),
+                        std::cmp::Ordering::Less => {
+                            (days_in_month(year, month) + 1).checked_sub(n.unsigned_abs().into())?
+                        }
+                    };
+
+                    chrono::NaiveDate::from_ymd_opt(year, month.number_from_month(), day).map(Ok)
+                })
+                .collect(),
+            EventDescriptionData::NthWeekdayOfYear { n, weekday } => {
+                let event_day = match n.cmp(&0) {
+                    std::cmp::Ordering::Equal => {
+                        anyhow::bail!("nth weekday cannot be 0");
+                    }
+                    std::cmp::Ordering::Greater => {
+                        find_date(year, Month::January, 1, weekday, 1)
+                            + chrono::Duration::weeks((n - 1).into())
+                    }
+                    std::cmp::Ordering::Less => {
+                        find_date(year, Month::December, 31, weekday, -1)
+                            + chrono::Duration::weeks((n + 1).into())
+                    }
+                };
+
+                Ok((event_day.year() == year)
+                    .then_some(event_day)
+                    .into_iter()
+                    .collect())
+            }
+            EventDescriptionData::HebrewDate { month, day } => {
+                // The Hebrew year starts in the autumn, so a Gregorian year overlaps two of them
+                Ok([year + 3760, year + 3761]
+                    .into_iter()
+                    .filter_map(|hebrew_year| {
+                        let hebrew_year = HebrewYear(hebrew_year.into());
+
+                        hebrew_year.date(month.number(hebrew_year.is_leap()), day)
+                    })
+                    .filter(|date| date.year() == year)
+                    .collect())
+            }
+            EventDescriptionData::HijriDate { month, day } => {
+                // Hijri years are about 11 days shorter than Gregorian years, so a Gregorian year overlaps two or three of them
+                let hijri_year = (i64::from(year) - 622) * 33 / 32;
+
+                Ok((hijri_year - 1..=hijri_year + 2)
+                    .filter_map(|hijri_year| HijriYear(hijri_year).date(month, day))
+                    .filter(|date| date.year() == year)
+                    .collect())
+            }
+            EventDescriptionData::ChineseDate { month, day } => {
+                // The 12th month of the previous Chinese year can be in January or February.
+                // Years outside the table have no dates, as neighbouring years are also rendered
+                Ok([year - 1, year]
+                    .into_iter()
+                    .filter_map(|chinese_year| ChineseYear(chinese_year).date(month, day))
+                    .filter(|date| date.year() == year)
+                    .collect())
+            }
+            EventDescriptionData::DaysAfter {
+                day_offset,
+                ref base,
+            } => base
+                .dates(year, non_working_days)?
+                .into_iter()
+                .map(|date| {
+                    date.checked_add_signed(chrono::Duration::days(day_offset.into()))
+                        .with_context(|| format!("No date {day_offset} days after {date}"))
+                })
+                .collect(),
+            EventDescriptionData::WeekdayOfIsoWeek { week, weekday } => {
+                // 28 December is always in the last ISO week of the year
+                let weeks_in_year = chrono::NaiveDate::from_ymd_opt(year, 12, 28)
+                    .with_context(|| format!("Invalid year {year}"))?
+                    .iso_week()
+                    .week();
+
+                let week = match week.cmp(&0) {
+                    std::cmp::Ordering::Equal => {
+                        anyhow::bail!("ISO week cannot be 0");
+                    }
+                    std::cmp::Ordering::Greater => Some(u32::from(week.unsigned_abs())),
+                    std::cmp::Ordering::Less => {
+                        (weeks_in_year + 1).checked_sub(week.unsigned_abs().into())
+                    }
+                };
+
+                Ok(week
+                    .and_then(|week| chrono::NaiveDate::from_isoywd_opt(year, week, weekday))
+                    .into_iter()
+                    .collect())
+            }
+        }
+    }
+}
+
+fn next_weekday(mut date: chrono::NaiveDate) -> anyhow::Result<chrono::NaiveDate> {
+    while date.weekday().is_weekend() {
+        date = date
+            .succ_opt()
+            .with_context(|| format!("No date after {date}"))?;
+    }
+
+    Ok(date)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HebrewMonth {
+    Nisan,
+    Iyyar,
+    Sivan,
+    Tammuz,
+    Av,
+    Elul,
+    Tishrei,
+    Heshvan,
+    Kislev,
+    Tevet,
+    Shevat,
+    // Adar in common years, Adar II in leap years
+    Adar,
+    AdarI,
+    AdarII,
+}
+
+impl HebrewMonth {
+    // Names which are prefixes of other names come after them
+    const NAMES: [(&'static str, Self); 21] = [
+        ("Nisan", Self::Nisan),
+        ("Iyyar", Self::Iyyar),
+        ("Iyar", Self::Iyyar),
+        ("Sivan", Self::Sivan),
+        ("Tammuz", Self::Tammuz),
+        ("Tamuz", Self::Tammuz),
+        ("Av", Self::Av),
+        ("Elul", Self::Elul),
+        ("Tishrei", Self::Tishrei),
+        ("Tishri", Self::Tishrei),
+        ("Heshvan", Self::Heshvan),
+        ("Cheshvan", Self::Heshvan),
+        ("Marheshvan", Self::Heshvan),
+        ("Marcheshvan", Self::Heshvan),
+        ("Kislev", Self::Kislev),
+        ("Tevet", Self::Tevet),
+        ("Shevat", Self::Shevat),
+        ("Shvat", Self::Shevat),
+        ("Adar II", Self::AdarII),
+        ("Adar I", Self::AdarI),
+        ("Adar", Self::Adar),
+    ];
+
+    /// The month number, counting from Nisan, in a year which is or isn't a leap year
+    fn number(self, is_leap_year: bool) -> u8 {
+        match self {
+            Self::Nisan => 1,
+            Self::Iyyar => 2,
+            Self::Sivan => 3,
+            Self::Tammuz => 4,
+            Self::Av => 5,
+            Self::Elul => 6,
+            Self::Tishrei => 7,
+            Self::Heshvan => 8,
+            Self::Kislev => 9,
+            Self::Tevet => 10,
+            Self::Shevat => 11,
+            Self::AdarI => 12,
+            Self::Adar | Self::AdarII => {
+                if is_leap_year {
+                    13
+                } else {
+                    12
+                }
+            }
+        }
+    }
+}
+
+/// A year of the Hebrew calendar, using the arithmetic from "Calendrical Calculations" by Dershowitz and Reingold
+struct HebrewYear(i64);
+
+impl HebrewYear {
+    // The day number of 1 Tishrei AM 1, counting 1 January 1 CE as day 1
+    const EPOCH: i64 = -1373427;
+
+    fn is_leap(&self) -> bool {
+        (7 * self.0 + 1).rem_euclid(19) < 7
+    }
+
+    fn last_month(&self) -> u8 {
+        if self.is_leap() {
+            13
+        } else {
+            12
+        }
+    }
+
+    /// Days from the epoch to the molad of Tishrei, delayed if it would put Rosh Hashanah on a Sunday, Wednesday or Friday
+    fn elapsed_days(year: i64) -> i64 {
+        let months_elapsed = (235 * year - 234).div_euclid(19);
+        let parts_elapsed = 12084 + 13753 * months_elapsed;
+        let days = 29 * months_elapsed + parts_elapsed.div_euclid(25920);
+
+        if (3 * (days + 1)).rem_euclid(7) < 3 {
+            days + 1
+        } else {
+            days
+        }
+    }
+
+    /// The day number of 1 Tishrei
+    fn new_year(year: i64) -> i64 {
+        let previous = Self::elapsed_days(year - 1);
+        let current = Self::elapsed_days(year);
+        let next = Self::elapsed_days(year + 1);
+
+        // Keep the year lengths valid
+        let delay = if next - current == 356 {
+            2
+        } else if current - previous == 382 {
+            1
+        } else {
+            0
+        };
+
+        Self::EPOCH + current + delay
+    }
+
+    fn days(&self) -> i64 {
+        Self::new_year(self.0 + 1) - Self::new_year(self.0)
+    }
+
+    fn days_in_month(&self, month: u8) -> u8 {
+        match month {
+            2 | 4 | 6 | 10 | 13 => 29,
+            12 if !self.is_leap() => 29,
+            // Heshvan is only long in complete years, and Kislev is only short in deficient years
+            8 if self.days() % 10 != 5 => 29,
+            9 if self.days() % 10 == 3 => 29,
+            _ => 30,
+        }
+    }
+
+    /// The months in the order they occur in the year, starting from Tishrei
+    fn months(&self) -> impl Iterator<Item = u8> {
+        (7..=self.last_month()).chain(1..7)
+    }
+
+    fn date(&self, month: u8, day: u8) -> Option<chrono::NaiveDate> {
+        if !(1..=self.last_month()).contains(&month)
+            || !(1..=self.days_in_month(month)).contains(&day)
+        {
+            return None;
+        }
+
+        let days_before_month = self
+            .months()
+            .take_while(|&candidate| candidate != month)
+            .map(|month| i64::from(self.days_in_month(month)))
+            .sum::<i64>();
+
+        chrono::NaiveDate::from_num_days_from_ce_opt(
+            (Self::new_year(self.0) + days_before_month + i64::from(day) - 1)
+                .try_into()
+                .ok()?,
+        )
+    }
+}
+
+/// A year of the tabular Islamic calendar, using the arithmetic from "Calendrical Calculations" by Dershowitz and Reingold
+struct HijriYear(i64);
+
+impl HijriYear {
+    // The day number of 1 Muharram AH 1, counting 1 January 1 CE as day 1
+    const EPOCH: i64 = 227015;
+
+    // Names which are prefixes of other names come after them
+    const MONTH_NAMES: [(&'static str, u8); 24] = [
+        ("Muharram", 1),
+        ("Safar", 2),
+        ("Rabi al-Awwal", 3),
+        ("Rabi' al-Awwal", 3),
+        ("Rabi II", 4),
+        ("Rabi I", 3),
+        ("Rabi al-Thani", 4),
+        ("Rabi' al-Thani", 4),
+        ("Jumada al-Awwal", 5),
+        ("Jumada al-Ula", 5),
+        ("Jumada II", 6),
+        ("Jumada I", 5),
+        ("Jumada al-Thani", 6),
+        ("Jumada al-Akhirah", 6),
+        ("Rajab", 7),
+        ("Shaban", 8),
+        ("Sha'ban", 8),
+        ("Ramadan", 9),
+        ("Shawwal", 10),
+        ("Dhu al-Qadah", 11),
+        ("Dhu al-Qa'dah", 11),
+        ("Dhul Qadah", 11),
+        ("Dhu al-Hijjah", 12),
+        ("Dhul Hijjah", 12),
+    ];
+
+    fn is_leap(&self) -> bool {
+        (14 + 11 * self.0).rem_euclid(30) < 11
+    }
+
+    fn days_in_month(&self, month: u8) -> u8 {
+        if month % 2 == 1 || (month == 12 && self.is_leap()) {
+            30
+        } else {
+            29
+        }
+    }
+
+    fn date(&self, month: u8, day: u8) -> Option<chrono::NaiveDate> {
+        if !(1..=12).contains(&month) || !(1..=self.days_in_month(month)).contains(&day) {
+            return None;
+        }
+
+        let month = i64::from(month);
+
+        let days = Self::EPOCH - 1
+            + (self.0 - 1) * 354
+            + (3 + 11 * self.0).div_euclid(30)
+            + 29 * (month - 1)
+            + month / 2
+            + i64::from(day);
+
+        chrono::NaiveDate::from_num_days_from_ce_opt(days.try_into().ok()?)
+    }
+}
+
+/// A year of the Chinese calendar
+struct ChineseYear(i32);
+
+impl ChineseYear {
+    const FIRST_YEAR: i32 = 1899;
+
+    /// For each year from 1899 to 2100, bits 15 to 4 are set if months 1 to 12 have 30 days rather than 29,
+    /// bits 3 to 0 are the number of the month followed by a leap month, if any,
+    /// and bit 16 is set if the leap month has 30 days
+    const YEARS: [u32; 202] = [
+        0x0ab50, 0x04bd8, 0x04ae0, 0x0a570, 0x054d5, 0x0d260, 0x0d950, 0x15554, 0x056a0, 0x09ad0,
+        0x055d2, 0x04ae0, 0x0a5b6, 0x0a4d0, 0x0d250, 0x1d255, 0x0b540, 0x0d6a0, 0x0ada2, 0x095b0,
+        0x14977, 0x04970, 0x0a4b0, 0x0b4b5, 0x06a50, 0x06d40, 0x1ab54, 0x02b60, 0x09570, 0x052f2,
+        0x04970, 0x06566, 0x0d4a0, 0x0ea50, 0x16a95, 0x05ad0, 0x02b60, 0x186e3, 0x092e0, 0x1c8d7,
+        0x0c950, 0x0d4a0, 0x1d8a6, 0x0b550, 0x056a0, 0x1a5b4, 0x025d0, 0x092d0, 0x0d2b2, 0x0a950,
+        0x0b557, 0x06ca0, 0x0b550, 0x15355, 0x04da0, 0x0a5b0, 0x14573, 0x052b0, 0x0a9a8, 0x0e950,
+        0x06aa0, 0x0aea6, 0x0ab50, 0x04b60, 0x0aae4, 0x0a570, 0x05260, 0x0f263, 0x0d950, 0x05b57,
+        0x056a0, 0x096d0, 0x04dd5, 0x04ad0, 0x0a4d0, 0x0d4d4, 0x0d250, 0x0d558, 0x0b540, 0x0b6a0,
+        0x195a6, 0x095b0, 0x049b0, 0x0a974, 0x0a4b0, 0x0b27a, 0x06a50, 0x06d40, 0x0af46, 0x0ab60,
+        0x09570, 0x04af5, 0x04970, 0x064b0, 0x074a3, 0x0ea50, 0x06b58, 0x05ac0, 0x0ab60, 0x096d5,
+        0x092e0, 0x0c960, 0x0d954, 0x0d4a0, 0x0da50, 0x07552, 0x056a0, 0x0abb7, 0x025d0, 0x092d0,
+        0x0cab5, 0x0a950, 0x0b4a0, 0x0baa4, 0x0ad50, 0x055d9, 0x04ba0, 0x0a5b0, 0x15176, 0x052b0,
+        0x0a930, 0x07954, 0x06aa0, 0x0ad50, 0x05b52, 0x04b60, 0x0a6e6, 0x0a4e0, 0x0d260, 0x0ea65,
+        0x0d530, 0x05aa0, 0x076a3, 0x096d0, 0x04afb, 0x04ad0, 0x0a4d0, 0x1d0b6, 0x0d250, 0x0d520,
+        0x0dd45, 0x0b5a0, 0x056d0, 0x055b2, 0x049b0, 0x0a577, 0x0a4b0, 0x0aa50, 0x1b255, 0x06d20,
+        0x0ada0, 0x14b63, 0x09370, 0x049f8, 0x04970, 0x064b0, 0x168a6, 0x0ea50, 0x06aa0, 0x1a6c4,
+        0x0aae0, 0x092e0, 0x0d2e3, 0x0c960, 0x0d557, 0x0d4a0, 0x0da50, 0x05d55, 0x056a0, 0x0a6d0,
+        0x055d4, 0x052d0, 0x0a9b8, 0x0a950, 0x0b4a0, 0x0b6a6, 0x0ad50, 0x055a0, 0x0aba4, 0x0a5b0,
+        0x052b0, 0x0b273, 0x06930, 0x07337, 0x06aa0, 0x0ad50, 0x14b55, 0x04b60, 0x0a570, 0x054e4,
+        0x0d160, 0x0e968, 0x0d520, 0x0daa0, 0x16aa6, 0x056d0, 0x04ae0, 0x0a9d4, 0x0a2d0, 0x0d150,
+        0x0f252, 0x0d520,
+    ];
+
+    fn info(&self) -> Option<u32> {
+        Self::YEARS
+            .get(usize::try_from(self.0 - Self::FIRST_YEAR).ok()?)
+            .copied()
+    }
+
+    /// The month numbers, whether they are leap months, and their lengths in the order they occur in the year
+    fn months(&self) -> Option<impl Iterator<Item = (u8, bool, u8)>> {
+        let info = self.info()?;
+        let leap_month = (info & 0xf) as u8;
+        let days = |is_long: bool| if is_long { 30 } else { 29 };
+
+        Some((1..=12).flat_map(move |month| {
+            std::iter::once((month, false, days(info & (0x10000 >> month) != 0)))
+                .chain((month == leap_month).then_some((month, true, days(info & 0x10000 != 0))))
+        }))
+    }
+
+    fn days(&self) -> Option<i64> {
+        Some(self.months()?.map(|(_, _, days)| i64::from(days)).sum())
+    }
+
+    fn new_year(&self) -> Option<chrono::NaiveDate> {
+        let days_before_year = (Self::FIRST_YEAR..self.0)
+            .map(|year| ChineseYear(year).days())
+            .sum::<Option<i64>>()?;
+
+        self.info()?;
+
+        chrono::NaiveDate::from_ymd_opt(Self::FIRST_YEAR, 2, 10)?
+            .checked_add_signed(chrono::Duration::days(days_before_year))
+    }
+
+    /// The date of a day in a month which isn't a leap month
+    fn date(&self, month: u8, day: u8) -> Option<chrono::NaiveDate> {
+        let mut days_before_month = 0;
+
+        for (candidate, is_leap_month, days) in self.months()? {
+            if (candidate, is_leap_month) == (month, false) {
+                if !(1..=days).contains(&day) {
+                    return None;
+                }
+
+                return self.new_year()?.checked_add_signed(chrono::Duration::days(
+                    days_before_month + i64::from(day) - 1,
+                ));
+            }
+
+            days_before_month += i64::from(days);
+        }
+
+        None
+    }
+}
+
+trait StrExt {
+    /// Like `strip_prefix`, but ignoring ASCII case. Text shorter than the prefix doesn't match,
+    /// so e.g. a line of just `in` isn't read as the start of `in 2027`
+    fn case_insensitive_strip_prefix<'a>(&'a self, prefix: &str) -> Option<&'a Self>;
+
+    fn first_n_characters(&self, n: usize) -> String;
+}
+
+impl StrExt for str {
+    fn case_insensitive_strip_prefix<'a>(&'a self, prefix: &str) -> Option<&'a Self> {
+        let mut chars = self.chars();
+
+        prefix
+            .chars()
+            .all(|a| chars.next().is_some_and(|b| a.eq_ignore_ascii_case(&b)))
+            .then_some(chars.as_str())
+    }
+
+    fn first_n_characters(&self, n: usize) -> String {
+        self.chars().take(n).collect()
+    }
+}
+
+/// The years in which an event occurs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Years {
+    from: Option<i32>,
+    until: Option<i32>,
+    // Only every `interval` years, counting from `from`
+    interval: u16,
+}
+
+impl Years {
+    const ALL: Self = Self {
+        from: None,
+        until: None,
+        interval: 1,
+    };
+
+    fn contains(self, year: i32) -> bool {
+        self.from.is_none_or(|from| {
+            from <= year && (year - from).rem_euclid(i32::from(self.interval)) == 0
+        }) && self.until.is_none_or(|until| year <= until)
+    }
+
+    fn parse_year(input: &str) -> anyhow::Result<(i32, &str)> {
+        let space_or_tab = |c: char| c == ' ' || c == '\t';
+
+        let input = input.trim_start();
+        let (year, rest) = input.split_once(space_or_tab).unwrap_or((input, ""));
+
+        Ok((
+            year.parse()
+                .with_context(|| format!("Invalid year {year}"))?,
+            rest.trim_start(),
+        ))
+    }
+
+    fn parse_until(from: Option<i32>, input: &str) -> anyhow::Result<(Option<i32>, &str)> {
+        let Some(input) = input.case_insensitive_strip_prefix("until ") else {
+            return Ok((None, input));
+        };
+
+        let (until, rest) = Self::parse_year(input)?;
+
+        if let Some(from) = from.filter(|&from| until < from) {
+            anyhow::bail!("{until} is before {from}");
+        }
+
+        Ok((Some(until), rest))
+    }
+
+    /// Parses `in 2027 `, `from 2026 `, `until 2028 `, `from 2026 until 2028 ` or `every 2 years from 2024 `,
+    /// where `every` may also be followed by `until`
+    fn parse(input: &str) -> anyhow::Result<Option<(Self, &str)>> {
+        if let Some(input) = input.case_insensitive_strip_prefix("in ") {
+            let (year, rest) = Self::parse_year(input)?;
+
+            return Ok(Some((
+                Self {
+                    from: Some(year),
+                    until: Some(year),
+                    interval: 1,
+                },
+                rest,
+            )));
+        }
+
+        if let Some(input) = input.case_insensitive_strip_prefix("every ") {
+            let space_or_tab = |c: char| c == ' ' || c == '\t';
+
+            let Some((interval, input)) = input.trim_start().split_once(space_or_tab) else {
+                anyhow::bail!("Invalid event: every {input}");
+            };
+
+            let interval = interval
+                .parse::<u16>()
+                .ok()
+                .filter(|&interval| interval > 0)
+                .with_context(|| format!("Invalid interval {interval}"))?;
+
+            let Some(input) = ["years from ", "year from "]
+                .into_iter()
+                .find_map(|prefix| input.trim_start().case_insensitive_strip_prefix(prefix))
+            else {
+                anyhow::bail!("Expected \"years from\" after \"every {interval}\"");
+            };
+
+            let (from, rest) = Self::parse_year(input)?;
+            let (until, rest) = Self::parse_until(Some(from), rest)?;
+
+            return Ok(Some((
+                Self {
+                    from: Some(from),
+                    until,
+                    interval,
+                },
+                rest,
+            )));
+        }
+
+        if let Some(input) = input.case_insensitive_strip_prefix("from ") {
+            let (from, rest) = Self::parse_year(input)?;
+            let (until, rest) = Self::parse_until(Some(from), rest)?;
+
+            return Ok(Some((
+                Self {
+                    from: Some(from),
+                    until,
+                    interval: 1,
+                },
+                rest,
+            )));
+        }
+
+        if input.case_insensitive_strip_prefix("until ").is_some() {
+            let (until, rest) = Self::parse_until(None, input)?;
+
+            return Ok(Some((
+                Self {
+                    from: None,
+                    until,
+                    interval: 1,
+                },
+                rest,
+            )));
+        }
+
+        Ok(None)
+    }
+}
+
+/// A change to the dates of an event
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Exception {
+    Month(Month),
+    Date(chrono::NaiveDate),
+    Move {
+        from: chrono::NaiveDate,
+        to: chrono::NaiveDate,
+    },
+}
+
+impl Exception {
+    /// Splits off the first word, which can be followed by spaces or tabs
+    fn split_word(input: &str) -> (&str, &str) {
+        let space_or_tab = |c: char| c == ' ' || c == '\t';
+
+        let input = input.trim_start();
+
+        input.split_once(space_or_tab).unwrap_or((input, ""))
+    }
+
+    fn parse_date(input: &str) -> anyhow::Result<(chrono::NaiveDate, &str)> {
+        let (date, rest) = Self::split_word(input);
+
+        Ok((
+            date.parse()
+                .with_context(|| format!("Invalid date {date}, expected YYYY-MM-DD"))?,
+            rest.trim_start(),
+        ))
+    }
+
+    /// Parses `except August `, `except 2027-12-08 ` or `move 2027-12-08 to 2027-12-15 `
+    fn parse(input: &str) -> anyhow::Result<Option<(Self, &str)>> {
+        if let Some(input) = input.case_insensitive_strip_prefix("except ") {
+            let (month_or_date, rest) = Self::split_word(input);
+
+            if let Ok(month) = Month::from_str(month_or_date) {
+                return Ok(Some((Self::Month(month), rest.trim_start())));
+            }
+
+            let (date, rest) = Self::parse_date(input)?;
+
+            return Ok(Some((Self::Date(date), rest)));
+        }
+
+        if let Some(input) = input.case_insensitive_strip_prefix("move ") {
+            let (from, rest) = Self::parse_date(input)?;
+
+            let Some(rest) = rest.case_insensitive_strip_prefix("to ") else {
+                anyhow::bail!("Expected \"to\" after \"move {from}\"");
+            };
+
+            let (to, rest) = Self::parse_date(rest)?;
+
+            return Ok(Some((Self::Move { from, to }, rest)));
+        }
+
+        Ok(None)
+    }
+
+    /// Applies the exceptions to `date`, returning None if it has been removed
+    fn apply(exceptions: &[Self], date: chrono::NaiveDate) -> Option<chrono::NaiveDate> {
+        exceptions
+            .iter()
+            .try_fold(date, |date, exception| match *exception {
+                Self::Month(month) if date.month() == month.number_from_month() => None,
+                Self::Date(excluded) if date == excluded => None,
+                Self::Move { from, to } if date == from => Some(to),
+                _ => Some(date),
+            })
+    }
+}
+
+#[derive(Clone)]
+struct EventDescription {
+    title: ArcStr,
+    time: Option<EventTime>,
+    attributes: EventAttributes,
+    data: EventDescriptionData,
+    group_id: GroupId,
+    years: Years,
+    exceptions: Vec<Exception>,
+}
+
+impl EventDescription {
+    /// Parses an optional count, a weekday and a relation, e.g. `4 Sunday before ` or `Monday on or after `
+    fn parse_weekday_relation(
+        input: &str,
+    ) -> anyhow::Result<Option<(u16, Weekday, DateRelation, &str)>> {
+        let (count, rest) = Self::split_count(input);
+
+        let Some((weekday, relation, rest)) = Weekday::ALL.iter().find_map(|&weekday| {
+            let rest = rest
+                .case_insensitive_strip_prefix(weekday.name())?
+                .strip_prefix(' ')?;
+
+            DateRelation::ALL.into_iter().find_map(|relation| {
+                Some((
+                    weekday,
+                    relation,
+                    rest.case_insensitive_strip_prefix(relation.name())?
+                        .strip_prefix(' ')?,
+                ))
+            })
+        }) else {
+            return Ok(None);
+        };
+
+        Ok(Some((Self::parse_count(count)?, weekday, relation, rest)))
+    }
+
+    fn split_count(input: &str) -> (Option<i32>, &str) {
+        input
+            .trim_start()
+            .split_once(' ')
+            .and_then(|(count, rest)| Some((count.parse::<i32>().ok()?, rest.trim_start())))
+            .map_or((None, input), |(count, rest)| (Some(count), rest))
+    }
+
+    fn parse_count(count: Option<i32>) -> anyhow::Result<u16> {
+        match count {
+            None => Ok(1),
+            Some(count) => u16::try_from(count)
+                .ok()
+                .filter(|&n| n > 0)
+                .with_context(|| format!("Invalid count {count}")),
+        }
+    }
+
+    /// Parses an optional count, the word `workdays` and a relation, e.g. `5 workdays before `
+    fn parse_workday_relation(input: &str) -> anyhow::Result<Option<(u16, DateRelation, &str)>> {
+        let (count, rest) = Self::split_count(input);
+
+        let Some((relation, rest)) = ["workdays ", "workday "].into_iter().find_map(|unit| {
+            let rest = rest.case_insensitive_strip_prefix(unit)?;
+
+            DateRelation::ALL.into_iter().find_map(|relation| {
+                Some((
+                    relation,
+                    rest.case_insensitive_strip_prefix(relation.name())?
+                        .strip_prefix(' ')?,
+                ))
+            })
+        }) else {
+            return Ok(None);
+        };
+
+        Ok(Some((Self::parse_count(count)?, relation, rest)))
+    }
+
+    /// Parses a day and a month name from another calendar, e.g. `15 Nisan ` or `1 Shawwal `
+    fn parse_named_month_date<'a, M: Copy>(
+        input: &'a str,
+        month_names: &[(&str, M)],
+    ) -> anyhow::Result<Option<(u8, M, &'a str)>> {
+        let space_or_tab = |c: char| c == ' ' || c == '\t';
+
+        let Some((day, name, month, rest)) =
+            input
+                .trim()
+                .split_once(space_or_tab)
+                .and_then(|(day, rest)| {
+                    month_names.iter().find_map(|&(name, month)| {
+                        Some((
+                            day,
+                            name,
+                            month,
+                            rest.trim_start()
+                                .case_insensitive_strip_prefix(name)?
+                                .strip_prefix(space_or_tab)?,
+                        ))
+                    })
+                })
+        else {
+            return Ok(None);
+        };
+
+        let day = day
+            .parse::<u8>()
+            .ok()
+            .filter(|day| (1..=30).contains(day))
+            .with_context(|| format!("Invalid date {day} {name}"))?;
+
+        Ok(Some((day, month, rest)))
+    }
+
+    /// Parses a count of days and a relation, e.g. `2 days before ` or `day after `
+    fn parse_day_relation(input: &str) -> anyhow::Result<Option<(i16, &str)>> {
+        let (count, rest) = Self::split_count(input);
+
+        let Some((sign, rest)) = ["days ", "day "].into_iter().find_map(|unit| {
+            let rest = rest.case_insensitive_strip_prefix(unit)?;
+
+            [(-1, "before "), (1, "after ")]
+                .into_iter()
+                .find_map(|(sign, relation)| {
+                    Some((sign, rest.case_insensitive_strip_prefix(relation)?))
+                })
+        }) else {
+            return Ok(None);
+        };
+
+        let count = Self::parse_count(count)?;
+
+        let day_offset =
+            i16::try_from(count).with_context(|| format!("Invalid number of days {count}"))?;
+
+        Ok(Some((sign * day_offset, rest)))
+    }
+
+    /// Splits the time from the start of a title and the attributes from the end
+    fn parse_title(title: &str) -> anyhow::Result<(ArcStr, Option<EventTime>, EventAttributes)> {
+        let (attributes, title) = EventAttributes::split_from_title(title)?;
+        let (time, title) = EventTime::split_from_title(title)?;
+
+        if title.is_empty() {
+            anyhow::bail!("Event has no title");
+        }
+
+        Ok((title.into(), time, attributes))
+    }
+
+    fn parse(input: &str, group_id: GroupId) -> anyhow::Result<Self> {
+        if let Some((years, rest)) = Years::parse(input)? {
+            let event = Self::parse(rest, group_id)?;
+
+            if event.years != Years::ALL {
+                anyhow::bail!("Event years given more than once: {input}");
+            }
+
+            return Ok(Self { years, ..event });
+        }
+
+        if let Some((exception, rest)) = Exception::parse(input)? {
+            let mut event = Self::parse(rest, group_id)?;
+
+            event.exceptions.insert(0, exception);
+
+            return Ok(event);
+        }
+
+        for (prefix, modifier) in [
+            (
+                "ho repl ",
+                EventDescriptionData::FuzzySunday as fn(_) -> EventDescriptionData,
+            ),
+            ("next weekday ", EventDescriptionData::NextWeekday),
+            ("nearest weekday ", EventDescriptionData::NearestWeekday),
+            ("substitute ", EventDescriptionData::Substitute),
+        ] {
+            if let Some(input) = input.case_insensitive_strip_prefix(prefix) {
+                let event = Self::parse(input, group_id)?;
+
+                return Ok(Self {
+                    data: modifier(Box::new(event.data)),
+                    ..event
+                });
+            }
+        }
+
+        // A title can start with words like a relation, e.g. `1 Friday After Work Drinks`,
+        // so the event is read without a relation if it isn't valid with one
+        match Self::parse_relative(input, group_id) {
+            None => Self::parse_date(input, group_id),
+            Some(Ok(event)) => Ok(event),
+            Some(Err(err)) => Self::parse_date(input, group_id).map_err(|_| err),
+        }
+    }
+
+    /// Parses an event relative to another event, e.g. `2 Sunday before 25 December Event`
+    fn parse_relative(input: &str, group_id: GroupId) -> Option<anyhow::Result<Self>> {
+        match Self::parse_weekday_relation(input) {
+            Ok(None) => {}
+            Ok(Some((n, weekday, relation, input))) => {
+                return Some(Self::parse(input, group_id).map(|event| Self {
+                    data: EventDescriptionData::NthWeekdayRelativeTo {
+                        n,
+                        weekday,
+                        relation,
+                        base: Box::new(event.data),
+                    },
+                    ..event
+                }));
+            }
+            Err(err) => return Some(Err(err)),
+        }
+
+        match Self::parse_workday_relation(input) {
+            Ok(None) => {}
+            Ok(Some((n, relation, input))) => {
+                return Some(Self::parse(input, group_id).map(|event| Self {
+                    data: EventDescriptionData::NthWorkdayRelativeTo {
+                        n,
+                        relation,
+                        base: Box::new(event.data),
+                    },
+                    ..event
+                }));
+            }
+            Err(err) => return Some(Err(err)),
+        }
+
+        match Self::parse_day_relation(input) {
+            Ok(None) => None,
+            Ok(Some((day_offset, input))) => Some(Self::parse(input, group_id).map(|event| Self {
+                data: EventDescriptionData::DaysAfter {
+                    day_offset,
+                    base: Box::new(event.data),
+                },
+                ..event
+            })),
+            Err(err) => Some(Err(err)),
+        }
+    }
+
+    /// Parses an event on a date which isn't relative to another event
+    fn parse_date(input: &str, group_id: GroupId) -> anyhow::Result<Self> {
+        let named_month_date = if let Some((day, month, title)) =
+            Self::parse_named_month_date(input, &HebrewMonth::NAMES)?
+        {
+            Some((EventDescriptionData::HebrewDate { month, day }, title))
+        } else if let Some((day, month, title)) =
+            Self::parse_named_month_date(input, &HijriYear::MONTH_NAMES)?
+        {
+            Some((EventDescriptionData::HijriDate { month, day }, title))
+        } else {
+            None
+        };
+
+        if let Some((data, title)) = named_month_date {
+            let (title, time, attributes) = Self::parse_title(title)?;
+
+            return Ok(Self {
+                title,
+                time,
+                attributes,
+                group_id,
+                years: Years::ALL,
+                exceptions: Vec::new(),
+                data,
+            });
+        }
+
+        let Some((index, category, title)) = Some(input).and_then(|input| {
+            let space_or_tab = |c: char| c == ' ' || c == '\t';
+
+            let (index, input) = input.trim().split_once(space_or_tab)?;
+            let (month_or_weekday, title) = input.trim().split_once(space_or_tab)?;
+
+            Some((index.trim(), month_or_weekday.trim(), title.trim()))
+        }) else {
+            anyhow::bail!("Invalid event: {input}")
+        };
+
+        let index = index
+            .parse::<i16>()
+            .with_context(|| format!("Invalid index {index}"))?;
+
+        let (title, time, attributes) = Self::parse_title(title)?;
+
+        let data = if category.eq_ignore_ascii_case("easter") {
+            EventDescriptionData::DaysAfterEaster { day_offset: index }
+        } else if category == "*" || category.eq_ignore_ascii_case("day") {
+            EventDescriptionData::DayOfMonth {
+                n: index,
+                month: None,
+            }
+        } else if let Some(month) = category.split_once('/').and_then(|(day, month)| {
+            day.eq_ignore_ascii_case("day")
+                .then(|| Month::from_str(month).ok())
+                .flatten()
+        }) {
+            EventDescriptionData::DayOfMonth {
+                n: index,
+                month: Some(month),
+            }
+        } else if category.eq_ignore_ascii_case("workday") {
+            EventDescriptionData::NthWorkdayOfMonth {
+                n: index,
+                month: None,
+            }
+        } else if let Some(month) = category.split_once('/').and_then(|(workday, month)| {
+            workday
+                .eq_ignore_ascii_case("workday")
+                .then(|| Month::from_str(month).ok())
+                .flatten()
+        }) {
+            EventDescriptionData::NthWorkdayOfMonth {
+                n: index,
+                month: Some(month),
+            }
+        } else if let Some(weekday) = category.split_once('/').and_then(|(weekday, year)| {
+            year.eq_ignore_ascii_case("year")
+                .then(|| Weekday::from_str(weekday).ok())
+                .flatten()
+        }) {
+            EventDescriptionData::NthWeekdayOfYear { n: index, weekday }
+        } else if let Some(weekday) = category.split_once('/').and_then(|(weekday, week)| {
+            week.eq_ignore_ascii_case("week")
+                .then(|| Weekday::from_str(weekday).ok())
+                .flatten()
+        }) {
+            EventDescriptionData::WeekdayOfIsoWeek {
+                week: index,
+                weekday,
+            }
+        } else if let Some(month) = category
+            .split_once('/')
+            .and_then(|(lunar, month)| lunar.eq_ignore_ascii_case("lunar").then_some(month))
+        {
+            EventDescriptionData::ChineseDate {
+                month: month
+                    .parse()
+                    .ok()
+                    .filter(|month| (1..=12).contains(month))
+                    .with_context(|| format!("Invalid lunar month {month}"))?,
+                day: u8::try_from(index)
+                    .ok()
+                    .filter(|day| (1..=30).contains(day))
+                    .with_context(|| format!("Invalid lunar date {index}/{month}"))?,
+            }
+        } else if let Some((weekday, month)) =
+            category.split_once('/').and_then(|(weekday, month)| {
+                Some((
+                    Weekday::from_str(weekday).ok()?,
+                    Month::from_str(month).ok()?,
+                ))
+            })
+        {
+            EventDescriptionData::NthWeekdayOfMonth {
+                n: index,
+                weekday,
+                month: Some(month),
+            }
+        } else if let Ok(month) = Month::from_str(category) {
+            EventDescriptionData::FixedDate {
+                month,
+                // Checked against a leap year, so that 29 February is valid
+                day: u32::try_from(index)
+                    .ok()
+                    .filter(|day| (1..=days_in_month(2000, month)).contains(day))
+                    .with_context(|| format!("Invalid date {}/{index}", month.name()))?,
+            }
+        } else if let Ok(weekday) = Weekday::from_str(category) {
+            EventDescriptionData::NthWeekdayOfMonth {
+                n: index,
+                weekday,
+                month: None,
+            }
+        } else {
+            anyhow::bail!("Invalid event: {input}")
+        };
+
+        // 0 is only a valid index for some categories, e.g. `0 easter`
+        if let EventDescriptionData::NthWeekdayOfMonth { n: 0, .. }
+        | EventDescriptionData::DayOfMonth { n: 0, .. } = data
+        {
+            anyhow::bail!("Invalid index 0: {input}");
+        }
+
+        Ok(Self {
+            title,
+            time,
+            attributes,
+            group_id,
+            years: Years::ALL,
+            exceptions: Vec::new(),
+            data,
+        })
+    }
+}
+
+#[derive(Clone, Data, Lens)]
+struct EventGroupDescription {
+    #[data(ignore)]
+    id: GroupId,
+    #[data(ignore)]
+    title: ArcStr,
+    #[data(ignore)]
+    style: Option<ArcStr>,
+    #[data(ignore)]
+    is_non_working: bool,
+    // Replaces the weekend background of the group's dates
+    #[data(ignore)]
+    non_working_class: Option<ArcStr>,
+    #[data(ignore)]
+    is_day_wide: bool,
+    #[data(ignore)]
+    priority: i32,
+    #[data(ignore)]
+    events: Vector<EventDescription>,
+    is_selected: bool,
+}
+
+impl EventGroupDescription {
+    /// The dates of each event in the group.
+    ///
+    /// Substitute events which fall on a weekend are moved to the next weekday which isn't taken by another event in the group,
+    /// in the order they appear in the file, so that e.g. Christmas Day and Boxing Day don't share a substitute day.
+    ///
+    /// Exceptions are applied last, so they refer to the dates which would otherwise be shown.
+    fn event_dates(
+        &self,
+        year: i32,
+        non_working_days: &NonWorkingDays,
+    ) -> anyhow::Result<Vec<(&EventDescription, Vec<chrono::NaiveDate>)>> {
+        let mut event_dates = self
+            .events
+            .iter()
+            .map(|event| {
+                if !event.years.contains(year) {
+                    return Ok((event, Vec::new()));
+                }
+
+                let dates = match event.data {
+                    EventDescriptionData::Substitute(ref base) => {
+                        base.dates(year, non_working_days)?
+                    }
+                    ref data => data.dates(year, non_working_days)?,
+                };
+
+                Ok((event, dates))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut taken_dates = event_dates
+            .iter()
+            .flat_map(|(_, dates)| dates.iter().copied())
+            .filter(|date| !date.weekday().is_weekend())
+            .collect::<HashSet<_>>();
+
+        for (event, dates) in &mut event_dates {
+            if !matches!(event.data, EventDescriptionData::Substitute(_)) {
+                continue;
+            }
+
+            for date in dates.iter_mut() {
+                if !date.weekday().is_weekend() {
+                    continue;
+                }
+
+                while date.weekday().is_weekend() || taken_dates.contains(date) {
+                    *date = date
+                        .succ_opt()
+                        .with_context(|| format!("No substitute day after {date}"))?;
+                }
+
+                taken_dates.insert(*date);
+            }
+        }
+
+        for (event, dates) in &mut event_dates {
+            *dates = dates
+                .iter()
+                .filter_map(|&date| Exception::apply(&event.exceptions, date))
+                .collect();
+        }
+
+        Ok(event_dates)
+    }
+}
+
+#[derive(Clone)]
+struct ErrorMessage(ArcStr);
+
+impl ErrorMessage {
+    fn new(err: anyhow::Error) -> Self {
+        Self(format!("{err:?}").into())
+    }
+}
+
+impl Data for ErrorMessage {
+    fn same(&self, other: &Self) -> bool {
+        std::sync::Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl druid::piet::TextStorage for ErrorMessage {
+    fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl druid::text::TextStorage for ErrorMessage {}
+
+#[derive(Clone, Data, Lens)]
+struct AppState {
+    error_message: Option<ErrorMessage>,
+    year: i32,
+    output: Output,
+    max_events: MaxEvents,
+    page_setup: PageSetup,
+    // User templates which replace the built-in templates
+    #[data(ignore)]
+    templates_directory: Option<std::path::PathBuf>,
+    themes: Vector<Theme>,
+    theme_index: usize,
+    event_group_descriptions: Vector<EventGroupDescription>,
+}
+
+impl AppState {
+    fn render_calendar(&self) -> anyhow::Result<String> {
+        let year = self.year;
+
+        let first_day = chrono::NaiveDate::from_ymd_opt(year, 1, 1).unwrap();
+        let last_day = chrono::NaiveDate::from_ymd_opt(year, 12, 31).unwrap();
+
+        // The planner has whole weeks, so it includes a few days of the neighbouring years
+        let (first_day, last_day) = match self.output {
+            Output::WeeklyPlanner => (
+                first_day.week(Weekday::Mon).first_day(),
+                last_day.week(Weekday::Mon).last_day(),
+            ),
+            _ => (first_day, last_day),
+        };
+
+        let non_working_days = NonWorkingDays::new(&self.event_group_descriptions, year)?;
+
+        let mut calendar_events = HashMap::new();
+        let mut day_wide_events = HashMap::new();
+        let mut non_working_classes = HashMap::new();
+
+        for event_group_description in &self.event_group_descriptions {
+            if !event_group_description.is_selected {
+                continue;
+            }
+
+            // Rules for the neighbouring years can land in this year, e.g. ISO weeks or
+            // "Sunday before 1 January", so evaluate those too and keep the dates which are shown
+            let event_dates_by_year = (year - 1..=year + 1)
+                .map(|year| event_group_description.event_dates(year, &non_working_days))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+
+            for event_index in 0..event_group_description.events.len() {
+                for (
+                    &EventDescription {
+                        ref title,
+                        time,
+                        ref attributes,
+                        group_id,
+                        ..
+                    },
+                    dates,
+                ) in event_dates_by_year
+                    .iter()
+                    .map(|event_dates| &event_dates[event_index])
+                {
+                    for &date in dates
+                        .iter()
+                        .filter(|&&date| first_day <= date && date <= last_day)
+                    {
+                        if event_group_description.is_non_working {
+                            non_working_classes.entry(date).or_insert_with(|| {
+                                event_group_description
+                                    .non_working_class
+                                    .clone()
+                                    .unwrap_or_else(|| SHADED_BACKGROUND_CLASS.into())
+                            });
+                        }
+
+                        if event_group_description.is_day_wide {
+                            &mut day_wide_events
+                        } else {
+                            &mut calendar_events
+                        }
+                        .entry(date)
+                        .or_insert_with(Vec::new)
+                        .push(EventWithGroupId {
+                            title: title.clone(),
+                            time,
+                            attributes: attributes.clone(),
+                            group_id,
+                            priority: attributes
+                                .priority
+                                .unwrap_or(event_group_description.priority),
+                        });
+                    }
+                }
+            }
+        }
+
+        // Events with a higher priority come first, then those without a time, then the rest in time order
+        for events in calendar_events.values_mut() {
+            events.sort_by_key(|event: &EventWithGroupId| {
+                (Reverse(event.priority), event.time.map(|time| time.start))
+            });
+        }
+
+        let calendar_event_styles = CalendarEventStyles(
             self.event_group_descriptions
                 .iter()
-                .filter_map(|event_group_description| {
-                    event_group_description
-                        .is_selected
-                        .then(|| {
-                            Some((
-                                event_group_description.id,
-                                event_group_description.style.clone()?,
-                            ))
-                        })
-                        .flatten()
+                .filter_map(|event_group_description| {
+                    event_group_description
+                        .is_selected
+                        .then(|| {
+                            Some((
+                                event_group_description.id,
+                                event_group_description.non_working_class.clone(),
+                                event_group_description.style.clone()?,
+                            ))
+                        })
+                        .flatten()
+                })
+                .collect(),
+        );
+
+        let mut overflow = Vec::new();
+
+        // Timed events go in the slot of the hour they start, if there is one, and don't count towards the maximum
+        let mut day_events = |date: chrono::NaiveDate,
+                              max_events: usize,
+                              slots: &mut [JournalSlot]|
+         -> DayEvents {
+            let mut unslotted_events = Vec::new();
+
+            for event in calendar_events.remove(&date).unwrap_or_default() {
+                let slot = event
+                    .time
+                    .and_then(|time| slots.iter_mut().find(|slot| slot.hour == time.start.hour()));
+
+                match slot {
+                    Some(slot) => slot.events.push(event),
+                    None => unslotted_events.push(event),
+                }
+            }
+
+            let (events, hidden_events) = EventWithGroupId::fit(unslotted_events, max_events);
+
+            let more_events = MoreEvents(hidden_events.len());
+
+            if !hidden_events.is_empty() {
+                overflow.push(OverflowDay {
+                    date,
+                    events: hidden_events,
+                });
+            }
+
+            DayEvents {
+                events,
+                more_events,
+                day_wide_events: DayWideEvents(day_wide_events.remove(&date).unwrap_or_default()),
+                non_working_class: non_working_classes.remove(&date),
+            }
+        };
+
+        let max_events = self.max_events.get(&self.output);
+
+        let page_style = self.page_setup.page_style(&self.output);
+
+        let theme_style = ThemeStyle(
+            self.themes
+                .get(self.theme_index)
+                .map(|theme| theme.variables.clone())
+                .unwrap_or_default(),
+        );
+
+        let templates_directory = self.templates_directory.as_deref();
+
+        match self.output {
+            Output::MonthlyCalendar => MonthlyCalendar {
+                calendar_event_styles,
+                page_style,
+                theme_style,
+                events: MONTHS
+                    .iter()
+                    .map(|&month| {
+                        let days_before_start =
+                            chrono::NaiveDate::from_ymd_opt(year, month.number_from_month(), 1)
+                                .unwrap()
+                                .weekday()
+                                .num_days_from_monday() as usize;
+
+                        let mut cells = std::iter::repeat_with(|| CalendarCell::Empty)
+                            .take(days_before_start)
+                            .chain((1..=days_in_month(year, month)).map(|day| {
+                                CalendarCell::Day {
+                                    day: EventDay { day },
+                                    day_events: day_events(
+                                        chrono::NaiveDate::from_ymd_opt(
+                                            year,
+                                            month.number_from_month(),
+                                            day,
+                                        )
+                                        .unwrap(),
+                                        max_events,
+                                        &mut [],
+                                    ),
+                                }
+                            }))
+                            .chain(std::iter::repeat_with(|| CalendarCell::Empty))
+                            .take(MonthlyCalendar::CELLS_PER_MONTH)
+                            .collect_vec();
+
+                        MonthlyCalendar::add_mini_months(&mut cells, year, month);
+
+                        cells.push(CalendarCell::MonthAndYear { month, year });
+
+                        cells
+                    })
+                    .collect(),
+                overflow,
+            }
+            .render_with_user_template(templates_directory),
+            Output::YearlyCalendar { split_in_two } => {
+                let mut months = MONTHS
+                    .iter()
+                    .map(|&month| {
+                        let days_before_start =
+                            chrono::NaiveDate::from_ymd_opt(year, month.number_from_month(), 1)
+                                .unwrap()
+                                .weekday()
+                                .num_days_from_monday() as usize;
+
+                        YearlyCalendarMonth {
+                            month,
+                            days: std::iter::repeat_with(|| YearlyCalendarDay::Empty {
+                                weekday: (),
+                            })
+                            .take(days_before_start)
+                            .chain((1..=days_in_month(year, month)).map(|day| {
+                                YearlyCalendarDay::Day {
+                                    weekday: (),
+                                    day: EventDay { day },
+                                    day_events: day_events(
+                                        chrono::NaiveDate::from_ymd_opt(
+                                            year,
+                                            month.number_from_month(),
+                                            day,
+                                        )
+                                        .unwrap(),
+                                        max_events,
+                                        &mut [],
+                                    ),
+                                }
+                            }))
+                            .chain(std::iter::repeat_with(|| YearlyCalendarDay::Empty {
+                                weekday: (),
+                            }))
+                            .take(YearlyCalendar::ROWS_COUNT)
+                            .zip(weekdays(Weekday::Mon))
+                            .map(|(day, weekday)| day.with_weekday(weekday))
+                            .collect(),
+                        }
+                    })
+                    .collect_vec();
+
+                YearlyCalendar {
+                    title: if split_in_two { "Half-Year" } else { "Year" },
+                    calendar_event_styles,
+                    page_style,
+                    theme_style,
+                    year,
+                    weekday_titles: weekdays(Weekday::Mon)
+                        .take(YearlyCalendar::ROWS_COUNT)
+                        .collect(),
+                    pages: if split_in_two {
+                        let latter_months = months.split_off(6);
+                        vec![
+                            YearlyCalendarPage { months },
+                            YearlyCalendarPage {
+                                months: latter_months,
+                            },
+                        ]
+                    } else {
+                        vec![YearlyCalendarPage { months }]
+                    },
+                    overflow,
+                }
+                .render_with_user_template(templates_directory)
+            }
+            Output::Diary { booklet } => {
+                let mut pages = MONTHS
+                    .iter()
+                    .flat_map(|&month| {
+                        let mut days = (1..=days_in_month(year, month))
+                            .map(|day| {
+                                let date = chrono::NaiveDate::from_ymd_opt(
+                                    year,
+                                    month.number_from_month(),
+                                    day,
+                                )
+                                .unwrap();
+
+                                DiaryCell::Day {
+                                    weekday: date.weekday(),
+                                    day: EventDay { day },
+                                    day_events: day_events(date, max_events, &mut []),
+                                }
+                            })
+                            .chain(std::iter::repeat_with(|| DiaryCell::Empty));
+
+                        [
+                            DiaryPage {
+                                month: Some(month),
+                                cells: days.by_ref().take(16).collect(),
+                            },
+                            DiaryPage {
+                                month: Some(month),
+                                cells: days.by_ref().take(15).collect(),
+                            },
+                        ]
+                    })
+                    .collect_vec();
+
+                if booklet {
+                    pages = Diary::impose_booklet(pages);
+                }
+
+                Diary {
+                    calendar_event_styles,
+                    page_style,
+                    theme_style,
+                    booklet,
+                    pages: pages
+                        .into_iter()
+                        .chunks(Diary::PAGES_PER_SHEET_SIDE)
+                        .into_iter()
+                        .map(Vec::from_iter)
+                        .collect(),
+                    overflow,
+                }
+                .render_with_user_template(templates_directory)
+            }
+            Output::WeeklyPlanner => WeeklyPlanner {
+                calendar_event_styles,
+                page_style,
+                theme_style,
+                weeks: first_day
+                    .iter_days()
+                    .take_while(|&date| date <= last_day)
+                    .map(|date| WeeklyPlannerDay {
+                        date,
+                        day_events: day_events(date, max_events, &mut []),
+                    })
+                    .chunks(7)
+                    .into_iter()
+                    .map(|days| WeeklyPlannerWeek {
+                        days: days.collect(),
+                    })
+                    .collect(),
+                overflow,
+            }
+            .render_with_user_template(templates_directory),
+            Output::DailyJournal { paper } => DailyJournal {
+                calendar_event_styles,
+                page_style,
+                theme_style,
+                paper,
+                pages: first_day
+                    .iter_days()
+                    .take_while(|&date| date <= last_day)
+                    .map(|date| {
+                        let mut slots = Vec::new();
+
+                        // Events starting during the hours of the page go in their hour instead of the list
+                        if paper == JournalPaper::Hourly {
+                            slots = (DailyJournal::FIRST_HOUR..=DailyJournal::LAST_HOUR)
+                                .map(|hour| JournalSlot {
+                                    hour,
+                                    events: Vec::new(),
+                                })
+                                .collect();
+                        }
+
+                        let day_events = day_events(date, max_events, &mut slots);
+
+                        let month = Month::try_from(date.month() as u8).unwrap();
+
+                        JournalPage {
+                            date,
+                            day_events,
+                            slots,
+                            mini_month: MiniMonth {
+                                highlighted_day: Some(date.day()),
+                                ..MiniMonth::new(year, month)
+                            },
+                        }
+                    })
+                    .collect(),
+                overflow,
+            }
+            .render_with_user_template(templates_directory),
+        }
+    }
+
+    fn show_calendar(&self, events: druid::ExtEventSink) -> anyhow::Result<()> {
+        let output = self.render_calendar()?;
+
+        fn worker(output: String) -> anyhow::Result<()> {
+            let listener = std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0))
+                .context("Failed to listen over HTTP")?;
+
+            let http_port = listener
+                .local_addr()
+                .context("Failed to get http address")?
+                .port();
+
+            webbrowser::open(&format!("http://127.0.0.1:{http_port}/"))
+                .context("Failed to open web browser")?;
+
+            let (mut socket, _remote_address) = listener
+                .accept()
+                .context("Failed to accept http connection")?;
+
+            for line in std::io::BufReader::new(&mut socket).lines() {
+                if line
+                    .context("Failed to read HTTP request")?
+                    .trim()
+                    .is_empty()
+                {
+                    break;
+                }
+            }
+
+            socket
+                .write_all(output.as_bytes())
+                .context("Failed to write HTTP response")
+        }
+
+        std::thread::spawn(move || {
+            if let Err(err) = worker(output) {
+                if let Err(err) =
+                    events.submit_command(SET_ERROR, ErrorMessage::new(err), druid::Target::Global)
+                {
+                    eprintln!("{err}");
+                }
+            }
+        });
+
+        Ok(())
+    }
+}
+
+macro_rules! create_keys {
+    ($($k:ident : $t:ty),* $(,)?) => {
+        $(
+            const $k: ::druid::Key::<$t> = ::druid::Key::new(concat!(env!("CARGO_PKG_NAME"), ".key.", stringify!($k)));
+        )*
+    };
+}
+
+create_keys!(
+    WIDGET_PADDING_INSETS: druid::Insets,
+    MARKDOWN_LIST_PADDING: f64,
+    EVENT_GROUP_TITLE: ArcStr,
+);
+
+macro_rules! create_selectors {
+    ($($k:ident : $t:ty),* $(,)?) => {
+        $(
+            const $k: ::druid::Selector::<$t> = ::druid::Selector::new(concat!(env!("CARGO_PKG_NAME"), ".selector.", stringify!($k)));
+        )*
+    };
+}
+
+create_selectors!(
+    SET_ERROR: ErrorMessage,
+    SHOW_HELP: (),
+    OPEN_LINK: String,
+);
+
+struct AppController;
+
+impl AppController {
+    fn cache_path() -> Option<std::path::PathBuf> {
+        let project_directories = directories::ProjectDirs::from("", "", "calendargenerator")?;
+        let config_directory = project_directories.config_local_dir();
+
+        std::fs::create_dir_all(config_directory)
+            .context("Failed to create config directory")
+            .map_err(|err| eprintln!("{err:?}"))
+            .ok();
+
+        Some(
+            [config_directory, "calendar_file_path.txt".as_ref()]
+                .into_iter()
+                .collect(),
+        )
+    }
+
+    /// A directory in the app's config directory, e.g. of user templates, which may not exist
+    fn config_directory(name: &str) -> Option<std::path::PathBuf> {
+        let project_directories = directories::ProjectDirs::from("", "", "calendargenerator")?;
+
+        Some(project_directories.config_local_dir().join(name))
+    }
+
+    fn open_calendar_dialog() -> druid::Command {
+        druid::commands::SHOW_OPEN_PANEL.with(
+            druid::FileDialogOptions::new()
+                .allowed_types(vec![druid::FileSpec::new("Calendar", &["txt"])])
+                .title("Open a calendar"),
+        )
+    }
+
+    fn help() -> impl Widget<AppState> {
+        Self::help_blocks(markdown::tokenize(include_str!("../README.md"))).scroll()
+    }
+
+    fn help_blocks(blocks: Vec<markdown::Block>) -> impl Widget<AppState> {
+        blocks.into_iter().fold(
+            Flex::column().cross_axis_alignment(druid::widget::CrossAxisAlignment::Start),
+            |column, block| match block {
+                markdown::Block::Header(spans, level) => column
+                    .with_spacer(match level {
+                        1 => 0.0,
+                        2 => 19.92,
+                        3 => 18.72,
+                        4 => 21.28,
+                        5 => 22.1776,
+                        6 => 24.9776,
+                        _ => 0.0,
+                    })
+                    .with_child(Self::help_spans_with_modify_text(spans, move |text| {
+                        text.size(match level {
+                            1 => 32.0,
+                            2 => 24.0,
+                            3 => 18.72,
+                            4 => 16.0,
+                            5 => 13.28,
+                            6 => 10.72,
+                            _ => 16.0,
+                        });
+
+                        text.text_color(druid::Color::rgb8(0x56, 0x9c, 0xd6));
+
+                        text.underline(true);
+                    }))
+                    .with_spacer(match level {
+                        1 => 21.44,
+                        2 => 19.92,
+                        3 => 18.72,
+                        4 => 21.28,
+                        5 => 22.1776,
+                        6 => 24.9776,
+                        _ => 0.0,
+                    }),
+                markdown::Block::Paragraph(spans) => column.with_child(Self::help_spans(spans)),
+                markdown::Block::Blockquote(_) => unimplemented!(),
+                markdown::Block::CodeBlock(_, _) => unimplemented!(),
+                markdown::Block::OrderedList(_, _) => unimplemented!(),
+                markdown::Block::UnorderedList(list) => column.with_child(
+                    list.into_iter()
+                        .map(|item| {
+                            let row = Flex::row()
+                                .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
+                                .with_spacer(MARKDOWN_LIST_PADDING)
+                                .with_child(Label::new("•"));
+
+                            match item {
+                                markdown::ListItem::Simple(spans) => {
+                                    row.with_child(Self::help_spans(spans))
+                                }
+                                markdown::ListItem::Paragraph(blocks) => {
+                                    row.with_child(Self::help_blocks(blocks))
+                                }
+                            }
+                        })
+                        .fold(
+                            Flex::column()
+                                .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start),
+                            Flex::with_child,
+                        ),
+                ),
+                markdown::Block::Raw(_) => unimplemented!(),
+                markdown::Block::Hr => unimplemented!(),
+            },
+        )
+    }
+
+    fn apply_help_spans(
+        rows: &mut Vec<druid::text::RichTextBuilder>,
+        modify_text: &dyn Fn(&mut druid::text::AttributesAdder),
+        spans: Vec<markdown::Span>,
+    ) {
+        for span in spans {
+            let mut text_attributes = match span {
+                markdown::Span::Break => {
+                    rows.push(druid::text::RichTextBuilder::new());
+                    continue;
+                }
+                markdown::Span::Text(text) => rows.last_mut().unwrap().push(&text),
+                markdown::Span::Code(text) => {
+                    let mut text_attributes = rows.last_mut().unwrap().push(&text);
+
+                    text_attributes
+                        .font_family(druid::FontFamily::MONOSPACE)
+                        .text_color(druid::Color::rgb8(0xce, 0x91, 0x78));
+
+                    text_attributes
+                }
+                markdown::Span::Link(text, url, _title) => {
+                    let mut text_attributes = rows.last_mut().unwrap().push(&text);
+
+                    text_attributes.link(OPEN_LINK.with(url)).underline(true);
+
+                    text_attributes
+                }
+                markdown::Span::Image(_, _, _) => unimplemented!(),
+                markdown::Span::Emphasis(spans) => {
+                    Self::apply_help_spans(
+                        rows,
+                        &|text| {
+                            modify_text(text);
+                            text.style(druid::FontStyle::Italic);
+                        },
+                        spans,
+                    );
+
+                    continue;
+                }
+                markdown::Span::Strong(spans) => {
+                    Self::apply_help_spans(
+                        rows,
+                        &|text| {
+                            modify_text(text);
+                            text.weight(druid::FontWeight::BOLD);
+                        },
+                        spans,
+                    );
+
+                    continue;
+                }
+            };
+
+            modify_text(&mut text_attributes);
+        }
+    }
+
+    fn help_spans(spans: Vec<markdown::Span>) -> impl Widget<AppState> {
+        Self::help_spans_with_modify_text(spans, |_| {})
+    }
+
+    fn help_spans_with_modify_text(
+        spans: Vec<markdown::Span>,
+        modify_text: impl Fn(&mut druid::text::AttributesAdder),
+    ) -> impl Widget<AppState> {
+        let mut rows = vec![druid::text::RichTextBuilder::new()];
+
+        Self::apply_help_spans(&mut rows, &modify_text, spans);
+
+        rows.into_iter()
+            .map(|text| Label::raw().lens(druid::lens::Constant(text.build())))
+            .fold(Flex::column(), Flex::with_child)
+    }
+
+    fn parse_calendar(
+        calendar_file: &std::path::Path,
+    ) -> anyhow::Result<Vector<EventGroupDescription>> {
+        let calendar_text = std::fs::read_to_string(calendar_file)
+            .with_context(|| format!("Failed to read {}", calendar_file.display()))?;
+
+        Self::parse_calendar_text(&calendar_text)
+    }
+
+    fn parse_calendar_text(calendar_text: &str) -> anyhow::Result<Vector<EventGroupDescription>> {
+        let mut event_group_descriptions = Vec::<EventGroupDescription>::new();
+
+        Self::parse_calendar_text_into(calendar_text, &mut event_group_descriptions)?;
+
+        Ok(event_group_descriptions.into())
+    }
+
+    /// Adds the holiday packs which aren't already in the calendar, so that they can be selected
+    fn with_holiday_packs(
+        event_group_descriptions: Vector<EventGroupDescription>,
+    ) -> anyhow::Result<Vector<EventGroupDescription>> {
+        let mut event_group_descriptions = event_group_descriptions.into_iter().collect::<Vec<_>>();
+
+        for (id, calendar_text) in HOLIDAY_PACKS {
+            let pack_start = event_group_descriptions.len();
+
+            Self::parse_calendar_text_into(calendar_text, &mut event_group_descriptions)
+                .with_context(|| format!("Failed to parse holidays {id}"))?;
+
+            let (calendar_groups, pack_groups) = event_group_descriptions.split_at(pack_start);
+
+            if pack_groups.iter().all(|pack_group| {
+                calendar_groups
+                    .iter()
+                    .any(|calendar_group| calendar_group.title == pack_group.title)
+            }) {
+                event_group_descriptions.truncate(pack_start);
+            }
+        }
+
+        Ok(event_group_descriptions.into())
+    }
+
+    fn parse_calendar_text_into(
+        calendar_text: &str,
+        event_group_descriptions: &mut Vec<EventGroupDescription>,
+    ) -> anyhow::Result<()> {
+        // Events can't continue a group from before a directive
+        let mut is_in_group = false;
+
+        for (line_num, line) in calendar_text.lines().enumerate() {
+            let line_num = line_num + 1;
+
+            let line = line.trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(directive) = line.strip_prefix('!') {
+                let Some(pack_id) = directive.case_insensitive_strip_prefix("holidays ") else {
+                    anyhow::bail!("Error on line {line_num}: Unknown directive {line}");
+                };
+
+                let pack_id = pack_id.trim();
+
+                let Some((_, pack_text)) = HOLIDAY_PACKS
+                    .into_iter()
+                    .find(|(id, _)| id.eq_ignore_ascii_case(pack_id))
+                else {
+                    anyhow::bail!(
+                        "Error on line {line_num}: Unknown holidays {pack_id}, expected one of {}",
+                        HOLIDAY_PACKS.iter().map(|(id, _)| id).join(", ")
+                    );
+                };
+
+                Self::parse_calendar_text_into(pack_text, event_group_descriptions)
+                    .with_context(|| format!("Failed to parse holidays {pack_id}"))?;
+
+                is_in_group = false;
+            } else if let Some(line) = line.strip_prefix('[') {
+                let Some(title_and_style) = line.strip_suffix(']') else {
+                    anyhow::bail!(
+                        "Error on line {line_num}: Event Group titles must end with a ']'"
+                    );
+                };
+
+                let (title, declarations) = title_and_style
+                    .split_once(':')
+                    .unwrap_or((title_and_style, ""));
+
+                let mut is_non_working = false;
+                let mut non_working_class = None;
+                let mut is_day_wide = false;
+                let mut priority = 0;
+                // Declarations which aren't settings are kept exactly as written
+                let mut style = Vec::new();
+
+                for untrimmed_declaration in declarations.split(';') {
+                    let declaration = untrimmed_declaration.trim();
+
+                    let setting = |name: &str| {
+                        declaration
+                            .split_once('=')
+                            .filter(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+                            .map(|(_, value)| value.trim())
+                    };
+
+                    if declaration.eq_ignore_ascii_case("non-working") {
+                        is_non_working = true;
+                    } else if let Some(class) = setting("non-working") {
+                        if !is_class_name(class) {
+                            anyhow::bail!(
+                                "Error on line {line_num}: Invalid non-working class {class}"
+                            );
+                        }
+
+                        is_non_working = true;
+                        non_working_class = Some(class.into());
+                    } else if declaration.eq_ignore_ascii_case("day-wide") {
+                        is_day_wide = true;
+                    } else if let Some(value) = setting("priority") {
+                        priority = value.parse().with_context(|| {
+                            format!("Error on line {line_num}: Invalid priority {value}")
+                        })?;
+                    } else {
+                        style.push(untrimmed_declaration);
+                    }
+                }
+
+                let style = style.join(";");
+
+                let id = GroupId::Group(event_group_descriptions.len());
+
+                event_group_descriptions.push(EventGroupDescription {
+                    id,
+                    title: title.trim().into(),
+                    style: (!style.trim().is_empty()).then(|| style.into()),
+                    is_non_working,
+                    non_working_class,
+                    is_day_wide,
+                    priority,
+                    events: Vector::new(),
+                    is_selected: false,
+                });
+
+                is_in_group = true;
+            } else {
+                let Some(current_group) =
+                    event_group_descriptions.last_mut().filter(|_| is_in_group)
+                else {
+                    anyhow::bail!(
+                        "Error on line {line_num}: Events must come after an event group title"
+                    );
+                };
+
+                let event = EventDescription::parse(line, current_group.id)?;
+
+                // Day-wide events style the day with their group, so they can't have their own style
+                if current_group.is_day_wide && event.attributes != EventAttributes::default() {
+                    anyhow::bail!(
+                        "Error on line {line_num}: Events in day-wide groups can't have attributes"
+                    );
+                }
+
+                current_group.events.push_back(event)
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<W: Widget<AppState>> druid::widget::Controller<AppState, W> for AppController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &druid::Event,
+        data: &mut AppState,
+        env: &Env,
+    ) {
+        if let druid::Event::WindowConnected = event {
+            if let Some(events) = Self::cache_path().and_then(|cache_path| {
+                let path = std::fs::read_to_string(cache_path).ok()?;
+
+                AppController::parse_calendar(path.trim().as_ref())
+                    .and_then(AppController::with_holiday_packs)
+                    .ok()
+            }) {
+                data.event_group_descriptions = events;
+            } else {
+                match Self::with_holiday_packs(Vector::new()) {
+                    Ok(event_group_descriptions) => {
+                        data.event_group_descriptions = event_group_descriptions
+                    }
+                    Err(err) => data.error_message = Some(ErrorMessage::new(err)),
+                }
+
+                ctx.submit_command(Self::open_calendar_dialog());
+            }
+        }
+
+        child.event(ctx, event, data, env)
+    }
+}
+
+impl druid::AppDelegate<AppState> for AppController {
+    fn command(
+        &mut self,
+        ctx: &mut druid::DelegateCtx,
+        _target: druid::Target,
+        command: &druid::Command,
+        data: &mut AppState,
+        _env: &Env,
+    ) -> druid::Handled {
+        if let Some(error) = command.get(SET_ERROR) {
+            data.error_message = Some(error.clone());
+
+            druid::Handled::Yes
+        } else if let Some(()) = command.get(SHOW_HELP) {
+            ctx.new_window::<AppState>(
+                druid::WindowDesc::new(AppController::help().controller(AppController))
+                    .title("Help"),
+            );
+
+            druid::Handled::Yes
+        } else if let Some(url) = command.get(OPEN_LINK) {
+            if let Err(err) = webbrowser::open(url).context("Failed to open browser") {
+                data.error_message = Some(ErrorMessage::new(err));
+            }
+
+            druid::Handled::Yes
+        } else if let Some(calendar_file) = command.get(druid::commands::OPEN_FILE) {
+            if let Err(err) = Self::parse_calendar(calendar_file.path())
+                .and_then(Self::with_holiday_packs)
+                .and_then(|event_group_descriptions| {
+                    data.event_group_descriptions = event_group_descriptions;
+
+                    if let Some(cache_path) = Self::cache_path() {
+                        std::fs::write(
+                            cache_path,
+                            calendar_file.path().as_os_str().as_encoded_bytes(),
+                        )
+                        .context("Failed to write cached calendar path")?;
+                    }
+
+                    Ok(())
+                })
+            {
+                data.error_message = Some(ErrorMessage::new(err));
+            }
+
+            druid::Handled::Yes
+        } else {
+            druid::Handled::No
+        }
+    }
+}
+
+fn app_view() -> impl Widget<AppState> {
+    Flex::column()
+        .with_child(
+            druid::widget::Maybe::or_empty(|| {
+                Flex::column()
+                    .with_child(
+                        Flex::column()
+                            .with_child(Label::new("Error!"))
+                            .with_default_spacer()
+                            .with_child(Label::raw())
+                            .border(
+                                druid::theme::BORDER_DARK,
+                                druid::theme::TEXTBOX_BORDER_WIDTH,
+                            )
+                            .expand_width(),
+                    )
+                    .with_default_spacer()
+                    .expand_width()
+            })
+            .lens(AppState::error_message),
+        )
+        .with_child(
+            Flex::column()
+                .with_child(Label::new("Year"))
+                .with_default_spacer()
+                .with_child(
+                    Flex::row()
+                        .with_child(Label::dynamic(|data, _env| format!("{data}")))
+                        .with_child(Stepper::new()),
+                )
+                .with_default_spacer()
+                .border(
+                    druid::theme::BORDER_DARK,
+                    druid::theme::TEXTBOX_BORDER_WIDTH,
+                )
+                .expand_width()
+                .lens(AppState::year.then(druid::lens::Map::new(
+                    |&year: &i32| year.into(),
+                    |current_year, new_year: f64| *current_year = new_year as i32,
+                ))),
+        )
+        .with_default_spacer()
+        .with_child(
+            Flex::column()
+                .with_child(Label::new("Calendar Type"))
+                .with_default_spacer()
+                .with_child(
+                    RadioGroup::column([
+                        ("Month", Output::MonthlyCalendar),
+                        (
+                            "Year",
+                            Output::YearlyCalendar {
+                                split_in_two: false,
+                            },
+                        ),
+                        ("Half-Year", Output::YearlyCalendar { split_in_two: true }),
+                        ("Diary", Output::Diary { booklet: false }),
+                        ("Diary (Booklet)", Output::Diary { booklet: true }),
+                        ("Week", Output::WeeklyPlanner),
+                        (
+                            "Journal (Ruled)",
+                            Output::DailyJournal {
+                                paper: JournalPaper::Ruled,
+                            },
+                        ),
+                        (
+                            "Journal (Dot Grid)",
+                            Output::DailyJournal {
+                                paper: JournalPaper::DotGrid,
+                            },
+                        ),
+                        (
+                            "Journal (Hourly)",
+                            Output::DailyJournal {
+                                paper: JournalPaper::Hourly,
+                            },
+                        ),
+                    ])
+                    .lens(AppState::output),
+                )
+                .with_default_spacer()
+                .with_child(
+                    Flex::row()
+                        .with_child(Label::dynamic(|max_events: &f64, _env| {
+                            format!("Up to {max_events} events per day")
+                        }))
+                        .with_child(Stepper::new().with_range(
+                            *MaxEvents::RANGE.start() as f64,
+                            *MaxEvents::RANGE.end() as f64,
+                        ))
+                        .lens(druid::lens::Map::new(
+                            |data: &AppState| data.max_events.get(&data.output) as f64,
+                            |data: &mut AppState, max_events: f64| {
+                                *data.max_events.get_mut(&data.output) = max_events as usize;
+                            },
+                        )),
+                )
+                .with_default_spacer()
+                .border(
+                    druid::theme::BORDER_DARK,
+                    druid::theme::TEXTBOX_BORDER_WIDTH,
+                )
+                .expand_width(),
+        )
+        .with_default_spacer()
+        .with_child(
+            Flex::column()
+                .with_child(Label::new("Page Setup"))
+                .with_default_spacer()
+                .with_child(
+                    RadioGroup::row(
+                        PaperSize::ALL.map(|paper_size| (paper_size.name(), paper_size)),
+                    )
+                    .lens(PageSetup::paper_size),
+                )
+                .with_child(
+                    RadioGroup::row([
+                        ("Auto", Orientation::Auto),
+                        ("Portrait", Orientation::Portrait),
+                        ("Landscape", Orientation::Landscape),
+                    ])
+                    .lens(PageSetup::orientation),
+                )
+                .with_child(
+                    Flex::row()
+                        .with_child(Label::dynamic(|margin: &f64, _env| {
+                            format!("Margin {margin}mm")
+                        }))
+                        .with_child(Stepper::new().with_range(
+                            *PageSetup::MARGIN_RANGE.start(),
+                            *PageSetup::MARGIN_RANGE.end(),
+                        ))
+                        .lens(PageSetup::margin),
+                )
+                .with_child(
+                    Flex::row()
+                        .with_child(Label::dynamic(|bleed: &f64, _env| {
+                            format!("Bleed {bleed}mm")
+                        }))
+                        .with_child(Stepper::new().with_range(
+                            *PageSetup::BLEED_RANGE.start(),
+                            *PageSetup::BLEED_RANGE.end(),
+                        ))
+                        .lens(PageSetup::bleed),
+                )
+                .with_default_spacer()
+                .border(
+                    druid::theme::BORDER_DARK,
+                    druid::theme::TEXTBOX_BORDER_WIDTH,
+                )
+                .expand_width()
+                .lens(AppState::page_setup),
+        )
+        .with_default_spacer()
+        .with_child(
+            Flex::column()
+                .with_child(Label::new("Theme"))
+                .with_default_spacer()
+                // The themes are only known once they are loaded
+                .with_child(druid::widget::ViewSwitcher::new(
+                    |data: &AppState, _env| data.themes.clone(),
+                    |themes, _data, _env| {
+                        RadioGroup::column(
+                            themes
+                                .iter()
+                                .enumerate()
+                                .map(|(index, theme)| (theme.name.clone(), index))
+                                .collect_vec(),
+                        )
+                        .lens(AppState::theme_index)
+                        .boxed()
+                    },
+                ))
+                .with_default_spacer()
+                .border(
+                    druid::theme::BORDER_DARK,
+                    druid::theme::TEXTBOX_BORDER_WIDTH,
+                )
+                .expand_width(),
+        )
+        .with_default_spacer()
+        .with_flex_child(
+            Flex::column()
+                .with_child(Label::new("Include Event Groups"))
+                .with_default_spacer()
+                .with_flex_child(
+                    List::new(|| {
+                        Checkbox::new(|_data: &bool, env: &Env| env.get(EVENT_GROUP_TITLE))
+                            .lens(EventGroupDescription::is_selected)
+                            .env_scope(|env: &mut Env, data: &EventGroupDescription| {
+                                env.set(EVENT_GROUP_TITLE, data.title.clone())
+                            })
+                    })
+                    .align_horizontal(druid::UnitPoint::CENTER)
+                    .scroll()
+                    .vertical()
+                    .lens(AppState::event_group_descriptions),
+                    1.0,
+                )
+                .border(
+                    druid::theme::BORDER_DARK,
+                    druid::theme::TEXTBOX_BORDER_WIDTH,
+                )
+                .expand(),
+            1.0,
+        )
+        .with_default_spacer()
+        .with_child(
+            Button::new("Create").on_click(|ctx, data: &mut AppState, _| {
+                if let Err(err) = data.show_calendar(ctx.get_external_handle()) {
+                    data.error_message = Some(ErrorMessage::new(err));
+                }
+            }),
+        )
+        .padding(WIDGET_PADDING_INSETS)
+}
+
+/// Creates printable calendars from a calendar file
+#[derive(clap::Parser)]
+struct Args {
+    #[clap(long, value_enum, default_value_t = PageSetup::DEFAULT.paper_size)]
+    paper_size: PaperSize,
+    #[clap(long, value_enum, default_value_t = PageSetup::DEFAULT.orientation)]
+    orientation: Orientation,
+    /// The page margins in millimetres, from 0 to 50
+    #[clap(
+        long,
+        default_value_t = PageSetup::DEFAULT.margin,
+        value_parser = |text: &str| PageSetup::parse_millimetres(text, PageSetup::MARGIN_RANGE),
+    )]
+    margin: f64,
+    /// How far in millimetres to print beyond the edge of each page, for trimming, from 0 to 10.
+    /// Browsers ignore it, but print tools which support the CSS `bleed` property use it
+    #[clap(
+        long,
+        default_value_t = PageSetup::DEFAULT.bleed,
+        value_parser = |text: &str| PageSetup::parse_millimetres(text, PageSetup::BLEED_RANGE),
+    )]
+    bleed: f64,
+    /// A directory of templates which replace the built-in templates of the same name.
+    /// Defaults to the templates directory in the app's config directory
+    #[clap(long)]
+    templates: Option<std::path::PathBuf>,
+    /// A directory of `.toml` theme files to choose from, as well as the bundled themes.
+    /// Defaults to the themes directory in the app's config directory
+    #[clap(long)]
+    themes: Option<std::path::PathBuf>,
+}
+
+fn main() -> anyhow::Result<()> {
+    let Args {
+        paper_size,
+        orientation,
+        margin,
+        bleed,
+        templates,
+        themes,
+    } = clap::Parser::parse();
+
+    let themes_directory = themes.or_else(|| AppController::config_directory("themes"));
+
+    // The app can still be used with the bundled themes if the user's themes are invalid
+    let (themes, error_message) = match Theme::load_all(themes_directory.as_deref()) {
+        Ok(themes) => (themes, None),
+        Err(err) => (Theme::load_all(None)?, Some(ErrorMessage::new(err))),
+    };
+
+    let app_name = "Create Calendar";
+
+    druid::AppLauncher::with_window(
+        druid::WindowDesc::new(app_view().controller(AppController))
+            .title(app_name)
+            .menu(move |_, _, _| {
+                druid::Menu::new(app_name)
+                    .entry(
+                        druid::MenuItem::new("Open Calendar")
+                            .command(AppController::open_calendar_dialog()),
+                    )
+                    .separator()
+                    .entry(druid::MenuItem::new("Help").command(SHOW_HELP))
+            })
+            .window_size(Size::new(800.0, 600.0)),
+    )
+    .delegate(AppController)
+    .configure_env(|env, _| {
+        let padding_horizontal = env.get(druid::theme::WIDGET_PADDING_HORIZONTAL);
+        let padding_vertical = env.get(druid::theme::WIDGET_PADDING_VERTICAL);
+
+        env.set(
+            WIDGET_PADDING_INSETS,
+            druid::Insets::uniform_xy(padding_horizontal, padding_vertical),
+        );
+
+        env.set(MARKDOWN_LIST_PADDING, 2.0 * padding_horizontal)
+    })
+    .launch(AppState {
+        error_message,
+        year: chrono::Local::now().year(),
+        output: Output::MonthlyCalendar,
+        max_events: MaxEvents::DEFAULT,
+        page_setup: PageSetup {
+            paper_size,
+            orientation,
+            margin,
+            bleed,
+        },
+        templates_directory: templates.or_else(|| AppController::config_directory("templates")),
+        themes,
+        theme_index: 0,
+        event_group_descriptions: Vector::new(),
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::{Datelike, Month, NaiveDate, Weekday};
+    use clap::Parser;
+    use itertools::Itertools;
+
+    use super::{
+        days_in_month, AppController, AppState, Args, CalendarCell, ChineseYear, DateRelation,
+        DayEvents, Diary, DiaryPage, EventAttributes, EventDay, EventDescription,
+        EventDescriptionData, EventGroupDescription, EventWithGroupId, Exception, GroupId,
+        HebrewMonth, HebrewYear, HijriYear, JournalPaper, MaxEvents, MiniMonth, MonthlyCalendar,
+        NonWorkingDays, Orientation, Output, PageSetup, PaperSize, StrExt, Theme, ThemeStyle,
+        WeekdayExt, Years, HOLIDAY_PACKS, MONTHS,
+    };
+
+    const YEARS: std::ops::RangeInclusive<i32> = 1600..=2400;
+
+    fn month_of(date: NaiveDate) -> Month {
+        MONTHS[date.month0() as usize]
+    }
+
+    fn days_of_year(year: i32) -> Vec<NaiveDate> {
+        NaiveDate::from_ymd_opt(year, 1, 1)
+            .unwrap()
+            .iter_days()
+            .take_while(|date| date.year() == year)
+            .collect()
+    }
+
+    /// The 50 dates nearest to a date which are in the relation to it, nearest first
+    fn relation_candidates(date: NaiveDate, relation: DateRelation) -> Vec<NaiveDate> {
+        match relation {
+            DateRelation::Before => date
+                .pred_opt()
+                .unwrap()
+                .iter_days()
+                .rev()
+                .take(50)
+                .collect(),
+            DateRelation::OnOrBefore => date.iter_days().rev().take(50).collect(),
+            DateRelation::After => date.succ_opt().unwrap().iter_days().take(50).collect(),
+            DateRelation::OnOrAfter => date.iter_days().take(50).collect(),
+        }
+    }
+
+    fn fixed_date(month: Month, day: u32) -> EventDescriptionData {
+        EventDescriptionData::FixedDate { month, day }
+    }
+
+    fn parse(input: &str) -> (String, EventDescriptionData) {
+        let EventDescription { title, data, .. } =
+            EventDescription::parse(input, GroupId::NoGroup).unwrap();
+
+        (title.to_string(), data)
+    }
+
+    fn snapshot_path(name: &str) -> std::path::PathBuf {
+        [env!("CARGO_MANIFEST_DIR"), "tests", "snapshots", name]
+            .into_iter()
+            .collect()
+    }
+
+    /// Compares `output` with the named snapshot, or overwrites the snapshot if `UPDATE_SNAPSHOTS` is set
+    fn assert_snapshot(name: &str, output: &str) {
+        // The build date and render date change on every run
+        let output = output
+            .lines()
+            .filter(|line| {
+                !line.contains(r#"<meta name="build-date""#)
+                    && !line.contains(r#"<meta name="date""#)
+            })
+            .map(|line| format!("{line}\n"))
+            .collect::<String>();
+
+        let path = snapshot_path(name);
+
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            std::fs::write(&path, output).unwrap();
+            return;
+        }
+
+        let expected = std::fs::read_to_string(&path)
+            .unwrap_or_else(|_| panic!("Failed to read {}", path.display()));
+
+        if let Some((line_num, (expected, actual))) = expected
+            .lines()
+            .zip(output.lines())
+            .enumerate()
+            .find(|(_, (expected, actual))| expected != actual)
+        {
+            panic!(
+                "{name} differs on line {}:\n  expected: {expected}\n    actual: {actual}\nRerun with UPDATE_SNAPSHOTS=1 to accept the new output",
+                line_num + 1
+            );
+        }
+
+        assert_eq!(
+            expected.lines().count(),
+            output.lines().count(),
+            "{name} has a different length. Rerun with UPDATE_SNAPSHOTS=1 to accept the new output"
+        );
+    }
+
+    fn group_event_dates(calendar_text: &str, year: i32) -> Vec<(String, Vec<NaiveDate>)> {
+        titled_event_dates(
+            &AppController::parse_calendar_text(calendar_text).unwrap()[0],
+            year,
+            &NonWorkingDays::default(),
+        )
+    }
+
+    fn titled_event_dates(
+        event_group_description: &EventGroupDescription,
+        year: i32,
+        non_working_days: &NonWorkingDays,
+    ) -> Vec<(String, Vec<NaiveDate>)> {
+        event_group_description
+            .event_dates(year, non_working_days)
+            .unwrap()
+            .into_iter()
+            .map(|(event, dates)| (event.title.to_string(), dates))
+            .collect()
+    }
+
+    fn ymd(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn render_snapshot_calendar(year: i32, output: Output) -> String {
+        render_snapshot_calendar_with_templates(year, output, None)
+    }
+
+    fn render_snapshot_calendar_with_templates(
+        year: i32,
+        output: Output,
+        templates_directory: Option<std::path::PathBuf>,
+    ) -> String {
+        render_calendar_text(
+            &std::fs::read_to_string(snapshot_path("calendar.txt")).unwrap(),
+            year,
+            output,
+            templates_directory,
+        )
+    }
+
+    /// Renders a calendar with all of its groups selected
+    fn render_calendar_text(
+        calendar_text: &str,
+        year: i32,
+        output: Output,
+        templates_directory: Option<std::path::PathBuf>,
+    ) -> String {
+        let mut event_group_descriptions =
+            AppController::parse_calendar_text(calendar_text).unwrap();
+
+        for event_group_description in event_group_descriptions.iter_mut() {
+            event_group_description.is_selected = true;
+        }
+
+        AppState {
+            error_message: None,
+            year,
+            output,
+            max_events: MaxEvents::default(),
+            page_setup: PageSetup::default(),
+            templates_directory,
+            themes: Theme::load_all(None).unwrap(),
+            theme_index: 0,
+            event_group_descriptions,
+        }
+        .render_calendar()
+        .unwrap()
+    }
+
+    #[test]
+    fn create_help() {
+        super::AppController::help();
+    }
+
+    #[test]
+    fn parse_fixed_date() {
+        assert_eq!(
+            parse("7 April Event Name"),
+            (
+                "Event Name".into(),
+                EventDescriptionData::FixedDate {
+                    month: Month::April,
+                    day: 7
+                }
+            )
+        );
+
+        assert_eq!(
+            parse("  25\tdec\t Christmas  Day "),
+            (
+                "Christmas  Day".into(),
+                EventDescriptionData::FixedDate {
+                    month: Month::December,
+                    day: 25
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn parse_nth_weekday_of_every_month() {
+        assert_eq!(
+            parse("2 Wednesday Event Name"),
+            (
+                "Event Name".into(),
+                EventDescriptionData::NthWeekdayOfMonth {
+                    n: 2,
+                    weekday: Weekday::Wed,
+                    month: None
+                }
+            )
+        );
+
+        assert_eq!(
+            parse("-1 fri Last Friday"),
+            (
+                "Last Friday".into(),
+                EventDescriptionData::NthWeekdayOfMonth {
+                    n: -1,
+                    weekday: Weekday::Fri,
+                    month: None
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn parse_nth_weekday_of_month() {
+        assert_eq!(
+            parse("3 Friday/July Event Name"),
+            (
+                "Event Name".into(),
+                EventDescriptionData::NthWeekdayOfMonth {
+                    n: 3,
+                    weekday: Weekday::Fri,
+                    month: Some(Month::July)
+                }
+            )
+        );
+
+        assert_eq!(
+            parse("-2 Monday/Aug Event Name"),
+            (
+                "Event Name".into(),
+                EventDescriptionData::NthWeekdayOfMonth {
+                    n: -2,
+                    weekday: Weekday::Mon,
+                    month: Some(Month::August)
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn parse_nth_weekday_of_year() {
+        assert_eq!(
+            parse("10 Monday/year Event Name"),
+            (
+                "Event Name".into(),
+                EventDescriptionData::NthWeekdayOfYear {
+                    n: 10,
+                    weekday: Weekday::Mon
+                }
+            )
+        );
+
+        assert_eq!(
+            parse("-1 fri/YEAR Event Name"),
+            (
+                "Event Name".into(),
+                EventDescriptionData::NthWeekdayOfYear {
+                    n: -1,
+                    weekday: Weekday::Fri
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn parse_weekday_of_iso_week() {
+        assert_eq!(
+            parse("32 Tuesday/week Event Name"),
+            (
+                "Event Name".into(),
+                EventDescriptionData::WeekdayOfIsoWeek {
+                    week: 32,
+                    weekday: Weekday::Tue
+                }
+            )
+        );
+
+        assert_eq!(
+            parse("-1 Sunday/Week Event Name"),
+            (
+                "Event Name".into(),
+                EventDescriptionData::WeekdayOfIsoWeek {
+                    week: -1,
+                    weekday: Weekday::Sun
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn parse_day_of_month() {
+        assert_eq!(
+            parse("15 * Event Name"),
+            (
+                "Event Name".into(),
+                EventDescriptionData::DayOfMonth { n: 15, month: None }
+            )
+        );
+
+        assert_eq!(
+            parse("-1 day Event Name"),
+            (
+                "Event Name".into(),
+                EventDescriptionData::DayOfMonth { n: -1, month: None }
+            )
+        );
+
+        assert_eq!(
+            parse("-3 Day/June Event Name"),
+            (
+                "Event Name".into(),
+                EventDescriptionData::DayOfMonth {
+                    n: -3,
+                    month: Some(Month::June)
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn parse_hebrew_dates() {
+        for (input, month, day) in [
+            ("15 Nisan Event Name", HebrewMonth::Nisan, 15),
+            ("1 tishri Event Name", HebrewMonth::Tishrei, 1),
+            ("14 Adar Event Name", HebrewMonth::Adar, 14),
+            ("14 Adar I Event Name", HebrewMonth::AdarI, 14),
+            ("14 ADAR II Event Name", HebrewMonth::AdarII, 14),
+            ("30 Cheshvan Event Name", HebrewMonth::Heshvan, 30),
+        ] {
+            assert_eq!(
+                parse(input),
+                (
+                    "Event Name".into(),
+                    EventDescriptionData::HebrewDate { month, day }
+                ),
+                "{input}"
+            );
+        }
+
+        assert_eq!(
+            parse("9 Av Ivy Day"),
+            (
+                "Ivy Day".into(),
+                EventDescriptionData::HebrewDate {
+                    month: HebrewMonth::Av,
+                    day: 9
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn parse_hijri_dates() {
+        for (input, month, day) in [
+            ("1 Muharram Event Name", 1, 1),
+            ("12 Rabi al-Awwal Event Name", 3, 12),
+            ("12 rabi i Event Name", 3, 12),
+            ("1 Rabi II Event Name", 4, 1),
+            ("1 Ramadan Event Name", 9, 1),
+            ("1 Shawwal Event Name", 10, 1),
+            ("10 Dhu al-Hijjah Event Name", 12, 10),
+        ] {
+            assert_eq!(
+                parse(input),
+                (
+                    "Event Name".into(),
+                    EventDescriptionData::HijriDate { month, day }
+                ),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_chinese_dates() {
+        assert_eq!(
+            parse("1 lunar/1 Event Name"),
+            (
+                "Event Name".into(),
+                EventDescriptionData::ChineseDate { month: 1, day: 1 }
+            )
+        );
+
+        assert_eq!(
+            parse("15 Lunar/8 Event Name"),
+            (
+                "Event Name".into(),
+                EventDescriptionData::ChineseDate { month: 8, day: 15 }
+            )
+        );
+    }
+
+    #[test]
+    fn parse_days_before_and_after() {
+        assert_eq!(
+            parse("1 day after 1 Shawwal Event Name"),
+            (
+                "Event Name".into(),
+                EventDescriptionData::DaysAfter {
+                    day_offset: 1,
+                    base: Box::new(EventDescriptionData::HijriDate { month: 10, day: 1 })
+                }
+            )
+        );
+
+        assert_eq!(
+            parse("day before 25 December Event Name"),
+            (
+                "Event Name".into(),
+                EventDescriptionData::DaysAfter {
+                    day_offset: -1,
+                    base: Box::new(fixed_date(Month::December, 25))
+                }
+            )
+        );
+
+        assert_eq!(
+            parse("3 Days Before -1 day Event Name"),
+            (
+                "Event Name".into(),
+                EventDescriptionData::DaysAfter {
+                    day_offset: -3,
+                    base: Box::new(EventDescriptionData::DayOfMonth { n: -1, month: None })
+                }
+            )
+        );
+
+        // A title can start like a relation
+        assert_eq!(
+            parse("-1 day Before close"),
+            (
+                "Before close".into(),
+                EventDescriptionData::DayOfMonth { n: -1, month: None }
+            )
+        );
+    }
+
+    #[test]
+    fn parse_days_after_easter() {
+        assert_eq!(
+            parse("1 easter Easter Monday"),
+            (
+                "Easter Monday".into(),
+                EventDescriptionData::DaysAfterEaster { day_offset: 1 }
+            )
+        );
+
+        assert_eq!(
+            parse("-2 EASTER Good Friday"),
+            (
+                "Good Friday".into(),
+                EventDescriptionData::DaysAfterEaster { day_offset: -2 }
+            )
+        );
+    }
+
+    #[test]
+    fn parse_fuzzy_sunday() {
+        assert_eq!(
+            parse("ho repl 15 August Assumption"),
+            (
+                "Assumption".into(),
+                EventDescriptionData::FuzzySunday(Box::new(fixed_date(Month::August, 15)))
+            )
+        );
+
+        assert_eq!(
+            parse("HO REPL 6 Thursday/January Epiphany"),
+            (
+                "Epiphany".into(),
+                EventDescriptionData::FuzzySunday(Box::new(
+                    EventDescriptionData::NthWeekdayOfMonth {
+                        n: 6,
+                        weekday: Weekday::Thu,
+                        month: Some(Month::January)
+                    }
+                ))
+            )
+        );
+    }
+
+    #[test]
+    fn parse_weekday_before() {
+        assert_eq!(
+            parse("Sunday before 25 December Fourth Sunday of Advent"),
+            (
+                "Fourth Sunday of Advent".into(),
+                EventDescriptionData::NthWeekdayRelativeTo {
+                    n: 1,
+                    weekday: Weekday::Sun,
+                    relation: DateRelation::Before,
+                    base: Box::new(fixed_date(Month::December, 25))
+                }
+            )
+        );
+
+        assert_eq!(
+            parse("friday BEFORE -1 easter Event"),
+            (
+                "Event".into(),
+                EventDescriptionData::NthWeekdayRelativeTo {
+                    n: 1,
+                    weekday: Weekday::Fri,
+                    relation: DateRelation::Before,
+                    base: Box::new(EventDescriptionData::DaysAfterEaster { day_offset: -1 })
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn parse_weekday_after() {
+        assert_eq!(
+            parse("Thursday after 4 Thursday/November Event"),
+            (
+                "Event".into(),
+                EventDescriptionData::NthWeekdayRelativeTo {
+                    n: 1,
+                    weekday: Weekday::Thu,
+                    relation: DateRelation::After,
+                    base: Box::new(EventDescriptionData::NthWeekdayOfMonth {
+                        n: 4,
+                        weekday: Weekday::Thu,
+                        month: Some(Month::November)
+                    })
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn parse_nth_weekday_relative_to() {
+        assert_eq!(
+            parse("4 Sunday before 25 December Advent Sunday"),
+            (
+                "Advent Sunday".into(),
+                EventDescriptionData::NthWeekdayRelativeTo {
+                    n: 4,
+                    weekday: Weekday::Sun,
+                    relation: DateRelation::Before,
+                    base: Box::new(fixed_date(Month::December, 25))
+                }
+            )
+        );
+
+        assert_eq!(
+            parse("4 thursday on or after 1 November Thanksgiving"),
+            (
+                "Thanksgiving".into(),
+                EventDescriptionData::NthWeekdayRelativeTo {
+                    n: 4,
+                    weekday: Weekday::Thu,
+                    relation: DateRelation::OnOrAfter,
+                    base: Box::new(fixed_date(Month::November, 1))
+                }
+            )
+        );
+
+        assert_eq!(
+            parse("Sunday On Or Before -1 easter Event"),
+            (
+                "Event".into(),
+                EventDescriptionData::NthWeekdayRelativeTo {
+                    n: 1,
+                    weekday: Weekday::Sun,
+                    relation: DateRelation::OnOrBefore,
+                    base: Box::new(EventDescriptionData::DaysAfterEaster { day_offset: -1 })
+                }
+            )
+        );
+
+        assert_eq!(
+            parse("2 Monday after 2 Monday Event"),
+            (
+                "Event".into(),
+                EventDescriptionData::NthWeekdayRelativeTo {
+                    n: 2,
+                    weekday: Weekday::Mon,
+                    relation: DateRelation::After,
+                    base: Box::new(EventDescriptionData::NthWeekdayOfMonth {
+                        n: 2,
+                        weekday: Weekday::Mon,
+                        month: None
+                    })
+                }
+            )
+        );
+
+        // Titles starting with a relation word are still read without one
+        assert_eq!(
+            parse("1 Friday After Work Drinks"),
+            (
+                "After Work Drinks".into(),
+                EventDescriptionData::NthWeekdayOfMonth {
+                    n: 1,
+                    weekday: Weekday::Fri,
+                    month: None
+                }
+            )
+        );
+
+        assert_eq!(
+            parse("-1 Friday after school Pizza Night"),
+            (
+                "after school Pizza Night".into(),
+                EventDescriptionData::NthWeekdayOfMonth {
+                    n: -1,
+                    weekday: Weekday::Fri,
+                    month: None
+                }
+            )
+        );
+
+        // The error of the relation is kept when the line isn't valid either way
+        let err = EventDescription::parse("2 workdays before 25 Smarch Event", GroupId::NoGroup)
+            .err()
+            .unwrap();
+        assert!(format!("{err:#}").contains("Smarch"), "{err:#}");
+    }
+
+    #[test]
+    fn nth_weekday_relative_to_dates() {
+        let dates = |input| {
+            parse(input)
+                .1
+                .dates(2024, &NonWorkingDays::default())
+                .unwrap()
+        };
+
+        assert_eq!(
+            dates("4 Sunday before 25 December Advent Sunday"),
+            vec![ymd(2024, 12, 1)]
+        );
+        assert_eq!(
+            dates("4 Thursday on or after 1 November Thanksgiving"),
+            vec![ymd(2024, 11, 28)]
+        );
+        assert_eq!(
+            dates("Sunday on or after 15 May Event"),
+            vec![ymd(2024, 5, 19)]
+        );
+        assert_eq!(
+            dates("Wednesday on or before 15 May Event"),
+            vec![ymd(2024, 5, 15)]
+        );
+        assert_eq!(
+            dates("Wednesday before 15 May Event"),
+            vec![ymd(2024, 5, 8)]
+        );
+    }
+
+    #[test]
+    fn parse_workdays() {
+        assert_eq!(
+            parse("2 workday Second working day"),
+            (
+                "Second working day".into(),
+                EventDescriptionData::NthWorkdayOfMonth { n: 2, month: None }
+            )
+        );
+
+        assert_eq!(
+            parse("-1 Workday/December Last working day"),
+            (
+                "Last working day".into(),
+                EventDescriptionData::NthWorkdayOfMonth {
+                    n: -1,
+                    month: Some(Month::December)
+                }
+            )
+        );
+
+        assert_eq!(
+            parse("5 workdays before -1 Friday Payroll cut-off"),
+            (
+                "Payroll cut-off".into(),
+                EventDescriptionData::NthWorkdayRelativeTo {
+                    n: 5,
+                    relation: DateRelation::Before,
+                    base: Box::new(EventDescriptionData::NthWeekdayOfMonth {
+                        n: -1,
+                        weekday: Weekday::Fri,
+                        month: None
+                    })
+                }
+            )
+        );
+
+        assert_eq!(
+            parse("workday on or after 1 May Event"),
+            (
+                "Event".into(),
+                EventDescriptionData::NthWorkdayRelativeTo {
+                    n: 1,
+                    relation: DateRelation::OnOrAfter,
+                    base: Box::new(fixed_date(Month::May, 1))
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn parse_weekday_substitution() {
+        assert_eq!(
+            parse("next weekday 1 January New Year's Day"),
+            (
+                "New Year's Day".into(),
+                EventDescriptionData::NextWeekday(Box::new(fixed_date(Month::January, 1)))
+            )
+        );
+
+        assert_eq!(
+            parse("Nearest Weekday 4 July Independence Day"),
+            (
+                "Independence Day".into(),
+                EventDescriptionData::NearestWeekday(Box::new(fixed_date(Month::July, 4)))
+            )
+        );
+
+        assert_eq!(
+            parse("substitute 25 December Christmas Day"),
+            (
+                "Christmas Day".into(),
+                EventDescriptionData::Substitute(Box::new(fixed_date(Month::December, 25)))
+            )
+        );
+    }
+
+    #[test]
+    fn parse_nested_modifiers() {
+        assert_eq!(
+            parse("Sunday before Sunday before 25 December Third Sunday of Advent"),
+            (
+                "Third Sunday of Advent".into(),
+                EventDescriptionData::NthWeekdayRelativeTo {
+                    n: 1,
+                    weekday: Weekday::Sun,
+                    relation: DateRelation::Before,
+                    base: Box::new(EventDescriptionData::NthWeekdayRelativeTo {
+                        n: 1,
+                        weekday: Weekday::Sun,
+                        relation: DateRelation::Before,
+                        base: Box::new(fixed_date(Month::December, 25))
+                    })
+                }
+            )
+        );
+
+        assert_eq!(
+            parse("ho repl Monday before -1 Monday/May Event"),
+            (
+                "Event".into(),
+                EventDescriptionData::FuzzySunday(Box::new(
+                    EventDescriptionData::NthWeekdayRelativeTo {
+                        n: 1,
+                        weekday: Weekday::Mon,
+                        relation: DateRelation::Before,
+                        base: Box::new(EventDescriptionData::NthWeekdayOfMonth {
+                            n: -1,
+                            weekday: Weekday::Mon,
+                            month: Some(Month::May)
+                        })
+                    }
+                ))
+            )
+        );
+
+        assert_eq!(
+            parse("Saturday before ho repl 1 May Event"),
+            (
+                "Event".into(),
+                EventDescriptionData::NthWeekdayRelativeTo {
+                    n: 1,
+                    weekday: Weekday::Sat,
+                    relation: DateRelation::Before,
+                    base: Box::new(EventDescriptionData::FuzzySunday(Box::new(fixed_date(
+                        Month::May,
+                        1
+                    ))))
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn parse_years() {
+        let years = |input| EventDescription::parse(input, GroupId::NoGroup).unwrap();
+
+        for (input, expected_years) in [
+            ("7 April Event", Years::ALL),
+            (
+                "in 2027 7 April Event",
+                Years {
+                    from: Some(2027),
+                    until: Some(2027),
+                    interval: 1,
+                },
+            ),
+            (
+                "From 2026 until 2028 7 April Event",
+                Years {
+                    from: Some(2026),
+                    until: Some(2028),
+                    interval: 1,
+                },
+            ),
+            (
+                "from 2026 7 April Event",
+                Years {
+                    from: Some(2026),
+                    until: None,
+                    interval: 1,
+                },
+            ),
+            (
+                "until 2028 7 April Event",
+                Years {
+                    from: None,
+                    until: Some(2028),
+                    interval: 1,
+                },
+            ),
+            (
+                "every 2 years from 2024 7 April Event",
+                Years {
+                    from: Some(2024),
+                    until: None,
+                    interval: 2,
+                },
+            ),
+            (
+                "every 4 years from 2024 until 2040 7 April Event",
+                Years {
+                    from: Some(2024),
+                    until: Some(2040),
+                    interval: 4,
+                },
+            ),
+            (
+                "from  2026\tuntil 2028\t7 April Event",
+                Years {
+                    from: Some(2026),
+                    until: Some(2028),
+                    interval: 1,
+                },
+            ),
+            (
+                "every 2\tyears from 2024\t7 April Event",
+                Years {
+                    from: Some(2024),
+                    until: None,
+                    interval: 2,
+                },
+            ),
+        ] {
+            let event = years(input);
+
+            assert_eq!(event.years, expected_years, "{input}");
+            assert_eq!(event.data, fixed_date(Month::April, 7), "{input}");
+            assert_eq!(&*event.title, "Event", "{input}");
+        }
+
+        let event = years("in 2027 substitute 25 December Christmas Day");
+
+        assert_eq!(event.years.from, Some(2027));
+        assert_eq!(
+            event.data,
+            EventDescriptionData::Substitute(Box::new(fixed_date(Month::December, 25)))
+        );
+
+        let event = years("Sunday before in 2027 25 December Event");
+
+        assert_eq!(event.years.until, Some(2027));
+        assert!(matches!(
+            event.data,
+            EventDescriptionData::NthWeekdayRelativeTo { .. }
+        ));
+    }
+
+    #[test]
+    fn years_contains() {
+        let years = |input| {
+            EventDescription::parse(input, GroupId::NoGroup)
+                .unwrap()
+                .years
+        };
+
+        let check = |input, expected: &[i32]| {
+            let years = years(input);
+
+            assert_eq!(
+                (2020..=2035)
+                    .filter(|&year| years.contains(year))
+                    .collect::<Vec<_>>(),
+                expected,
+                "{input}"
+            );
+        };
+
+        check("in 2027 1 June Event", &[2027]);
+        check("from 2026 until 2028 1 June Event", &[2026, 2027, 2028]);
+        check("from 2033 1 June Event", &[2033, 2034, 2035]);
+        check("until 2022 1 June Event", &[2020, 2021, 2022]);
+        check(
+            "every 2 years from 2024 1 June Event",
+            &[2024, 2026, 2028, 2030, 2032, 2034],
+        );
+        check(
+            "every 3 years from 2021 until 2030 1 June Event",
+            &[2021, 2024, 2027, 2030],
+        );
+    }
+
+    #[test]
+    fn year_limited_events_in_group() {
+        let calendar = "[Events]\n\
+            in 2027 7 April Conference\n\
+            every 2 years from 2024 2 Saturday/June Festival\n\
+            from 2026 until 2028 1 Monday/January Project kick-off\n";
+
+        assert_eq!(
+            group_event_dates(calendar, 2026),
+            vec![
+                ("Conference".into(), vec![]),
+                ("Festival".into(), vec![ymd(2026, 6, 13)]),
+                ("Project kick-off".into(), vec![ymd(2026, 1, 5)]),
+            ]
+        );
+
+        assert_eq!(
+            group_event_dates(calendar, 2027),
+            vec![
+                ("Conference".into(), vec![ymd(2027, 4, 7)]),
+                ("Festival".into(), vec![]),
+                ("Project kick-off".into(), vec![ymd(2027, 1, 4)]),
+            ]
+        );
+
+        assert_eq!(
+            group_event_dates(calendar, 2029),
+            vec![
+                ("Conference".into(), vec![]),
+                ("Festival".into(), vec![]),
+                ("Project kick-off".into(), vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_exceptions() {
+        let event = EventDescription::parse(
+            "except August move 2027-12-08 to 2027-12-15 except 2028-01-12 2 Wednesday Club night",
+            GroupId::NoGroup,
+        )
+        .unwrap();
+
+        assert_eq!(&*event.title, "Club night");
+        assert_eq!(
+            event.data,
+            EventDescriptionData::NthWeekdayOfMonth {
+                n: 2,
+                weekday: Weekday::Wed,
+                month: None
+            }
+        );
+        assert_eq!(
+            event.exceptions,
+            vec![
+                Exception::Month(Month::August),
+                Exception::Move {
+                    from: ymd(2027, 12, 8),
+                    to: ymd(2027, 12, 15)
+                },
+                Exception::Date(ymd(2028, 1, 12)),
+            ]
+        );
+
+        let event =
+            EventDescription::parse("in 2027 except jul 1 Friday Event", GroupId::NoGroup).unwrap();
+
+        assert_eq!(event.years.from, Some(2027));
+        assert_eq!(event.exceptions, vec![Exception::Month(Month::July)]);
+
+        let event = EventDescription::parse(
+            "except  jul\tmove 2027-12-08\tto 2027-12-15\t1 Friday Event",
+            GroupId::NoGroup,
+        )
+        .unwrap();
+
+        assert_eq!(&*event.title, "Event");
+        assert_eq!(
+            event.exceptions,
+            vec![
+                Exception::Month(Month::July),
+                Exception::Move {
+                    from: ymd(2027, 12, 8),
+                    to: ymd(2027, 12, 15)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn exceptions_in_group() {
+        let calendar = "[Clubs]\n\
+            except August move 2027-12-08 to 2027-12-15 except 2027-01-13 2 Wednesday Club night\n\
+            except 2027-12-27 substitute 25 December Christmas Day\n";
+
+        let [(_, club_nights), (_, christmas)] = &group_event_dates(calendar, 2027)[..] else {
+            panic!("Expected two events");
+        };
+
+        assert_eq!(
+            club_nights,
+            &[
+                ymd(2027, 2, 10),
+                ymd(2027, 3, 10),
+                ymd(2027, 4, 14),
+                ymd(2027, 5, 12),
+                ymd(2027, 6, 9),
+                ymd(2027, 7, 14),
+                ymd(2027, 9, 8),
+                ymd(2027, 10, 13),
+                ymd(2027, 11, 10),
+                ymd(2027, 12, 15),
+            ]
+        );
+
+        // Exceptions apply to the substitute day
+        assert_eq!(christmas, &[]);
+
+        let [(_, club_nights), (_, christmas)] = &group_event_dates(calendar, 2026)[..] else {
+            panic!("Expected two events");
+        };
+
+        assert_eq!(club_nights.len(), 11);
+        assert_eq!(christmas, &[ymd(2026, 12, 25)]);
+    }
+
+    #[test]
+    fn case_insensitive_strip_prefix() {
+        assert_eq!("IN 2027".case_insensitive_strip_prefix("in "), Some("2027"));
+        assert_eq!("in ".case_insensitive_strip_prefix("in "), Some(""));
+        assert_eq!("in".case_insensitive_strip_prefix("in "), None);
+        assert_eq!("".case_insensitive_strip_prefix("except "), None);
+        assert_eq!("exempt 2027".case_insensitive_strip_prefix("except "), None);
+    }
+
+    #[test]
+    fn parse_event_times() {
+        let time = |input: &str| {
+            let EventDescription { title, time, .. } =
+                EventDescription::parse(input, GroupId::NoGroup).unwrap();
+
+            (title.to_string(), time.map(|time| time.to_string()))
+        };
+
+        assert_eq!(time("7 April Dentist"), ("Dentist".into(), None));
+
+        assert_eq!(
+            time("7 April 14:30 Dentist"),
+            ("Dentist".into(), Some("14:30".into()))
+        );
+
+        assert_eq!(
+            time("7 April 9:05-15:15 Dentist"),
+            ("Dentist".into(), Some("09:05-15:15".into()))
+        );
+
+        assert_eq!(
+            time("in 2027 substitute 2 Wednesday 19:30-22:00 Club Night"),
+            ("Club Night".into(), Some("19:30-22:00".into()))
+        );
+
+        assert_eq!(
+            time("15 Nisan 18:00 Seder"),
+            ("Seder".into(), Some("18:00".into()))
+        );
+
+        assert_eq!(
+            time("7 April 2nd Anniversary"),
+            ("2nd Anniversary".into(), None)
+        );
+
+        // Overnight
+        assert_eq!(
+            time("31 December 22:00-02:00 Party"),
+            ("Party".into(), Some("22:00-02:00".into()))
+        );
+
+        // Words which don't look like times are part of the title
+        for title in [
+            "10:30am Brunch",
+            "1:1 review",
+            "14:30- Dentist",
+            "2:30:00 Race",
+        ] {
+            assert_eq!(
+                time(&format!("7 April {title}")),
+                (title.into(), None),
+                "{title:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_event_attributes() {
+        let attributes = |input: &str| {
+            let EventDescription {
+                title,
+                time,
+                attributes,
+                ..
+            } = EventDescription::parse(input, GroupId::NoGroup).unwrap();
+
+            (
+                title.to_string(),
+                time.map(|time| time.to_string()),
+                attributes,
+            )
+        };
+
+        assert_eq!(
+            attributes("7 April Alice {style: font-weight:bold; icon: 🎂}"),
+            (
+                "Alice".into(),
+                None,
+                EventAttributes {
+                    style: Some("font-weight:bold".into()),
+                    icon: Some("🎂".into()),
+                    class: None,
+                    priority: None,
+                }
+            )
+        );
+
+        assert_eq!(
+            attributes("7 April 14:30 Dentist {Style: color: blue; font-style:italic; class: appointment urgent}"),
+            (
+                "Dentist".into(),
+                Some("14:30".into()),
+                EventAttributes {
+                    style: Some("color: blue;font-style:italic".into()),
+                    icon: None,
+                    class: Some("appointment urgent".into()),
+                    priority: None,
+                }
+            )
+        );
+
+        for input in [
+            "7 April Party {BYO}",
+            "7 April Party {}",
+            "7 April Party {colour: red}",
+        ] {
+            assert_eq!(
+                attributes(input),
+                (
+                    input.strip_prefix("7 April ").unwrap().into(),
+                    None,
+                    EventAttributes::default()
+                ),
+                "{input}"
+            );
+        }
+
+        assert_eq!(
+            attributes("7 April Alice {priority: -2}"),
+            (
+                "Alice".into(),
+                None,
+                EventAttributes {
+                    priority: Some(-2),
+                    ..EventAttributes::default()
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn parse_invalid_events() {
+        for input in [
+            "",
+            "7",
+            "7 April",
+            "April 7 Event",
+            "7 Smarch Event",
+            "-1 April Event",
+            "x easter Event",
+            "99999 easter Event",
+            "1 Friday/Smarch Event",
+            "1 Funday/July Event",
+            "ho repl ",
+            "Sunday before Event",
+            "ho",
+            "Sunday",
+            "Sunday after Event",
+            "next weekday",
+            "substitute Event",
+            "0 Sunday before 25 December Event",
+            "-5 workdays before -1 Friday Event",
+            "0 workdays after 1 May Event",
+            "1 workday/Smarch Event",
+            "in 7 April Event",
+            "in twenty 7 April Event",
+            "from 2028 until 2026 7 April Event",
+            "every 0 years from 2024 7 April Event",
+            "every 2 years 7 April Event",
+            "every 2 years from 2024 until 2020 7 April Event",
+            "in 2027 in 2028 7 April Event",
+            "in 2027 Sunday before from 2026 7 April Event",
+            "in 2027",
+            "except Smarch 7 April Event",
+            "except 2027-13-01 7 April Event",
+            "except 7 April Event",
+            "move 2027-04-07 2027-04-08 7 April Event",
+            "move 2027-04-07 to 7 April Event",
+            "except August",
+            "0 days before 1 January Event",
+            "0 Shawwal Event",
+            "0 lunar/1 Event",
+            "31 lunar/1 Event",
+            "7 April 25:00 Event",
+            "31 April Event",
+            "30 February Event",
+            "0 April Event",
+            "7 April 14:30-24:00 Event",
+            "7 April 14:30-14:30 Event",
+            "7 April Event {icon: ★; colour: red}",
+            "7 April Event {icon:}",
+            "7 April Event {class: a\"b}",
+            "7 April Event {priority: high}",
+            "7 April {icon: 🎂}",
+            "1 lunar/13 Event",
+            "1 lunar/leap Event",
+            "0 Nisan Event",
+            "31 Nisan Event",
+        ] {
+            assert!(
+                EventDescription::parse(input, GroupId::NoGroup).is_err(),
+                "{input:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_calendar_groups() {
+        let event_group_descriptions =
+            AppController::parse_calendar(&snapshot_path("calendar.txt")).unwrap();
+
+        assert_eq!(
+            event_group_descriptions
+                .iter()
+                .map(|event_group_description| (
+                    event_group_description.id,
+                    event_group_description.title.to_string(),
+                    event_group_description.style.as_deref().map(str::to_string),
+                    event_group_description.events.len()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    GroupId::Group(0),
+                    "Bank Holidays".into(),
+                    Some(" color:red".into()),
+                    8
+                ),
+                (
+                    GroupId::Group(1),
+                    "Church".into(),
+                    Some(" color:blue;font-weight:bold".into()),
+                    5
+                ),
+                (GroupId::Group(2), "Clubs".into(), None, 6),
+                (
+                    GroupId::Group(3),
+                    "Deadlines".into(),
+                    Some(" font-style:italic".into()),
+                    6
+                ),
+                (
+                    GroupId::Group(4),
+                    "Jewish Holidays".into(),
+                    Some(" color:purple".into()),
+                    3
+                ),
+                (
+                    GroupId::Group(5),
+                    "Islamic Holidays".into(),
+                    Some(" color:green".into()),
+                    3
+                ),
+                (
+                    GroupId::Group(6),
+                    "Chinese Festivals".into(),
+                    Some(" color:darkorange".into()),
+                    3
+                ),
+                (
+                    GroupId::Group(7),
+                    "Term Dates".into(),
+                    Some(" background-color:lightcyan".into()),
+                    2
+                ),
+            ]
+        );
+
+        assert_eq!(
+            event_group_descriptions
+                .iter()
+                .map(|event_group_description| (
+                    event_group_description.priority,
+                    event_group_description.is_day_wide
+                ))
+                .collect_vec(),
+            [
+                (0, false),
+                (0, false),
+                (0, false),
+                (-1, false),
+                (0, false),
+                (0, false),
+                (0, false),
+                (0, true),
+            ]
+        );
+
+        assert!(AppController::parse_calendar_text("[Work: priority=high]\n").is_err());
+
+        let term_dates = &AppController::parse_calendar_text(
+            "[Term Dates: Day-Wide; Priority = 3; Non-Working=holiday]\n",
+        )
+        .unwrap()[0];
+        assert_eq!(
+            (
+                term_dates.is_day_wide,
+                term_dates.priority,
+                term_dates.non_working_class.as_deref()
+            ),
+            (true, 3, Some("holiday"))
+        );
+        assert_eq!(term_dates.style, None);
+
+        assert!(AppController::parse_calendar_text(
+            "[Term Dates: day-wide]\n1 September First day {icon: 🏫}\n"
+        )
+        .is_err());
+
+        let closures =
+            &AppController::parse_calendar_text("[Closures: non-working=closed; color:grey]\n")
+                .unwrap()[0];
+        assert!(closures.is_non_working);
+        assert_eq!(closures.non_working_class.as_deref(), Some("closed"));
+        assert_eq!(closures.style.as_deref(), Some(" color:grey"));
+
+        // Styles are kept as written
+        assert_eq!(
+            AppController::parse_calendar_text("[Church: color:blue ;; font-weight:bold ]\n")
+                .unwrap()[0]
+                .style
+                .as_deref(),
+            Some(" color:blue ;; font-weight:bold ")
+        );
+
+        assert!(AppController::parse_calendar_text("[Closures: non-working=a\"b]\n").is_err());
+
+        // The group's style is given to its days with their own class
+        let calendar = render_calendar_text(
+            "[Closures: non-working=closed shut; background-color:lightgrey]\n1 January Closed\n",
+            2024,
+            Output::MonthlyCalendar,
+            None,
+        );
+        assert!(calendar.contains("<span class=\"cell closed shut\">"));
+        assert!(calendar.contains(".eventgroup0, .closed.shut {  background-color:lightgrey }"));
+
+        assert!(event_group_descriptions
+            .iter()
+            .all(|event_group_description| event_group_description
+                .events
+                .iter()
+                .all(|event| event.group_id == event_group_description.id)));
+    }
+
+    #[test]
+    fn parse_non_working_groups() {
+        let event_group_descriptions = AppController::parse_calendar_text(
+            "[Bank Holidays: color:red; Non-Working]\n\
+            [Closures: non-working]\n\
+            [Other: font-weight:bold]\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            event_group_descriptions
+                .iter()
+                .map(|event_group_description| (
+                    event_group_description.style.as_deref().map(str::to_string),
+                    event_group_description.is_non_working
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (Some(" color:red".into()), true),
+                (None, true),
+                (Some(" font-weight:bold".into()), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn holiday_packs_are_valid() {
+        for (id, calendar_text) in HOLIDAY_PACKS {
+            let event_group_descriptions = AppController::parse_calendar_text(calendar_text)
+                .unwrap_or_else(|err| panic!("{id}: {err:?}"));
+
+            assert_eq!(event_group_descriptions.len(), 1, "{id}");
+            assert!(event_group_descriptions[0].is_non_working, "{id}");
+            assert!(!event_group_descriptions[0].events.is_empty(), "{id}");
+
+            for year in YEARS.step_by(50) {
+                event_group_descriptions[0]
+                    .event_dates(year, &NonWorkingDays::default())
+                    .unwrap_or_else(|err| panic!("{id} {year}: {err:?}"));
+            }
+        }
+    }
+
+    #[test]
+    fn holiday_pack_dates() {
+        let dates = |id: &str, year: i32| {
+            group_event_dates(&format!("!holidays {id}"), year)
+                .into_iter()
+                .flat_map(|(_, dates)| dates)
+                .sorted()
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            dates("gb-eng", 2024),
+            [
+                ymd(2024, 1, 1),
+                ymd(2024, 3, 29),
+                ymd(2024, 4, 1),
+                ymd(2024, 5, 6),
+                ymd(2024, 5, 27),
+                ymd(2024, 8, 26),
+                ymd(2024, 12, 25),
+                ymd(2024, 12, 26),
+            ]
+        );
+
+        assert_eq!(
+            dates("GB-ENG", 2022),
+            [
+                ymd(2022, 1, 3),
+                ymd(2022, 4, 15),
+                ymd(2022, 4, 18),
+                ymd(2022, 5, 2),
+                ymd(2022, 6, 2),
+                ymd(2022, 6, 3),
+                ymd(2022, 8, 29),
+                ymd(2022, 9, 19),
+                ymd(2022, 12, 26),
+                ymd(2022, 12, 27),
+            ]
+        );
+
+        assert_eq!(
+            dates("gb-sct", 2023),
+            [
+                ymd(2023, 1, 2),
+                ymd(2023, 1, 3),
+                ymd(2023, 4, 7),
+                ymd(2023, 5, 1),
+                ymd(2023, 5, 8),
+                ymd(2023, 5, 29),
+                ymd(2023, 8, 7),
+                ymd(2023, 11, 30),
+                ymd(2023, 12, 25),
+                ymd(2023, 12, 26),
+            ]
+        );
+
+        // New Year's Day 2022 was observed on the Friday before
+        assert_eq!(
+            dates("us", 2022),
+            [
+                ymd(2021, 12, 31),
+                ymd(2022, 1, 17),
+                ymd(2022, 2, 21),
+                ymd(2022, 5, 30),
+                ymd(2022, 6, 20),
+                ymd(2022, 7, 4),
+                ymd(2022, 9, 5),
+                ymd(2022, 10, 10),
+                ymd(2022, 11, 11),
+                ymd(2022, 11, 24),
+                ymd(2022, 12, 26),
+            ]
+        );
+
+        assert_eq!(
+            dates("de", 2024),
+            [
+                ymd(2024, 1, 1),
+                ymd(2024, 3, 29),
+                ymd(2024, 4, 1),
+                ymd(2024, 5, 1),
+                ymd(2024, 5, 9),
+                ymd(2024, 5, 20),
+                ymd(2024, 10, 3),
+                ymd(2024, 12, 25),
+                ymd(2024, 12, 26),
+            ]
+        );
+
+        assert_eq!(dates("de-sn", 2024), [ymd(2024, 10, 31), ymd(2024, 11, 20)]);
+
+        assert_eq!(
+            dates("fr", 2024),
+            [
+                ymd(2024, 1, 1),
+                ymd(2024, 4, 1),
+                ymd(2024, 5, 1),
+                ymd(2024, 5, 8),
+                ymd(2024, 5, 9),
+                ymd(2024, 5, 20),
+                ymd(2024, 7, 14),
+                ymd(2024, 8, 15),
+                ymd(2024, 11, 1),
+                ymd(2024, 11, 11),
+                ymd(2024, 12, 25),
+            ]
+        );
+    }
+
+    #[test]
+    fn holidays_directive() {
+        let event_group_descriptions = AppController::parse_calendar_text(
+            "[Birthdays]\n\
+            7 April Alice\n\
+            !holidays gb-eng\n\
+            !holidays de-by\n\
+            [Work]\n\
+            2 workday Invoices due\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            event_group_descriptions
+                .iter()
+                .map(|event_group_description| (
+                    event_group_description.id,
+                    event_group_description.title.to_string(),
+                    event_group_description.is_non_working,
+                    event_group_description
+                        .events
+                        .iter()
+                        .all(|event| event.group_id == event_group_description.id)
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (GroupId::Group(0), "Birthdays".into(), false, true),
+                (
+                    GroupId::Group(1),
+                    "England and Wales Bank Holidays".into(),
+                    true,
+                    true
+                ),
+                (
+                    GroupId::Group(2),
+                    "Bavaria Public Holidays".into(),
+                    true,
+                    true
+                ),
+                (GroupId::Group(3), "Work".into(), false, true),
+            ]
+        );
+
+        for calendar_text in [
+            "!holidays atlantis\n",
+            "!calendar gb-eng\n",
+            "[Birthdays]\n!holidays gb-eng\n7 April Alice\n",
+            "7 April Alice\n",
+        ] {
+            assert!(
+                AppController::parse_calendar_text(calendar_text).is_err(),
+                "{calendar_text:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn holiday_packs_in_group_list() {
+        let event_group_descriptions = AppController::with_holiday_packs(
+            AppController::parse_calendar_text("[Birthdays]\n7 April Alice\n!holidays us\n")
+                .unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(event_group_descriptions.len(), HOLIDAY_PACKS.len() + 1);
+
+        assert_eq!(
+            event_group_descriptions
+                .iter()
+                .filter(|event_group_description| {
+                    event_group_description.title.as_ref() == "US Federal Holidays"
+                })
+                .count(),
+            1
+        );
+
+        for (index, event_group_description) in event_group_descriptions.iter().enumerate() {
+            assert_eq!(event_group_description.id, GroupId::Group(index));
+            assert!(!event_group_description.is_selected);
+        }
+    }
+
+    #[test]
+    fn unselected_holiday_packs_keep_workdays() {
+        let mut event_group_descriptions = AppController::with_holiday_packs(
+            AppController::parse_calendar_text(
+                "[Deadlines]\n2 workday/January Second working day\n",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let second_working_day =
+            |event_group_descriptions: &druid::im::Vector<EventGroupDescription>| {
+                titled_event_dates(
+                    &event_group_descriptions[0],
+                    2024,
+                    &NonWorkingDays::new(event_group_descriptions, 2024).unwrap(),
+                )
+            };
+
+        assert_eq!(
+            second_working_day(&event_group_descriptions),
+            [("Second working day".into(), vec![ymd(2024, 1, 2)])]
+        );
+
+        for event_group_description in event_group_descriptions.iter_mut() {
+            event_group_description.is_selected =
+                event_group_description.title.as_ref() == "England and Wales Bank Holidays";
+        }
+
+        // Skipping New Year's Day
+        assert_eq!(
+            second_working_day(&event_group_descriptions),
+            [("Second working day".into(), vec![ymd(2024, 1, 3)])]
+        );
+    }
+
+    #[test]
+    fn workdays_skip_non_working_groups() {
+        let mut event_group_descriptions = AppController::parse_calendar_text(
+            "[Bank Holidays: non-working]\n\
+            1 January New Year's Day\n\
+            -2 easter Good Friday\n\
+            1 easter Easter Monday\n\
+            25 December Christmas Day\n\
+            [Deadlines]\n\
+            3 workdays after -1 Thursday/March Payroll cut-off\n\
+            2 workday/January Second working day\n\
+            1 workday after 24 December Next working day\n",
+        )
+        .unwrap();
+
+        // Unselected non-working groups don't affect workdays
+        let unselected_non_working_days =
+            NonWorkingDays::new(&event_group_descriptions, 2024).unwrap();
+
+        assert!(unselected_non_working_days.is_workday(ymd(2024, 12, 25)));
+
+        assert_eq!(
+            titled_event_dates(
+                &event_group_descriptions[1],
+                2024,
+                &unselected_non_working_days
+            ),
+            titled_event_dates(
+                &event_group_descriptions[1],
+                2024,
+                &NonWorkingDays::default()
+            )
+        );
+
+        for event_group_description in event_group_descriptions.iter_mut() {
+            event_group_description.is_selected = true;
+        }
+
+        let non_working_days = NonWorkingDays::new(&event_group_descriptions, 2024).unwrap();
+
+        assert_eq!(
+            event_group_descriptions[1]
+                .event_dates(2024, &non_working_days)
+                .unwrap()
+                .into_iter()
+                .map(|(event, dates)| (event.title.to_string(), dates))
+                .collect::<Vec<_>>(),
+            vec![
+                // Skipping Good Friday and Easter Monday
+                ("Payroll cut-off".into(), vec![ymd(2024, 4, 4)]),
+                ("Second working day".into(), vec![ymd(2024, 1, 3)]),
+                ("Next working day".into(), vec![ymd(2024, 12, 26)]),
+            ]
+        );
+
+        // Only weekends are skipped without any non-working groups
+        assert_eq!(
+            event_group_descriptions[1]
+                .event_dates(2024, &NonWorkingDays::default())
+                .unwrap()
+                .into_iter()
+                .map(|(_, dates)| dates)
+                .collect::<Vec<_>>(),
+            vec![
+                vec![ymd(2024, 4, 2)],
+                vec![ymd(2024, 1, 2)],
+                vec![ymd(2024, 12, 25)],
+            ]
+        );
+
+        // The following year's non-working days are included
+        let non_working_days = NonWorkingDays::new(&event_group_descriptions, 2025).unwrap();
+
+        assert!(!non_working_days.is_workday(ymd(2026, 1, 1)));
+        assert!(!non_working_days.is_workday(ymd(2024, 12, 25)));
+        assert!(non_working_days.is_workday(ymd(2025, 12, 24)));
+    }
+
+    #[test]
+    fn leap_day_in_other_years() {
+        // The neighbouring years are also evaluated, which mustn't fail for non-leap years
+        let calendar_text = "[Leap Days: non-working]\n29 February Leap Day\n\
+            [Deadlines]\n1 workday after 28 February Deadline\n";
+
+        for (year, has_leap_day) in [(2024, true), (2025, false)] {
+            let calendar = render_calendar_text(calendar_text, year, Output::MonthlyCalendar, None);
+
+            assert_eq!(calendar.contains("Leap Day"), has_leap_day, "{year}");
+            assert!(calendar.contains("Deadline"), "{year}");
+        }
+    }
+
+    #[test]
+    fn monthly_calendar_snapshot() {
+        assert_snapshot(
+            "monthly_calendar_2024.html",
+            &render_snapshot_calendar(2024, Output::MonthlyCalendar),
+        );
+    }
+
+    #[test]
+    fn yearly_calendar_snapshot() {
+        assert_snapshot(
+            "yearly_calendar_2025.html",
+            &render_snapshot_calendar(
+                2025,
+                Output::YearlyCalendar {
+                    split_in_two: false,
+                },
+            ),
+        );
+    }
+
+    #[test]
+    fn half_yearly_calendar_snapshot() {
+        assert_snapshot(
+            "half_yearly_calendar_2025.html",
+            &render_snapshot_calendar(2025, Output::YearlyCalendar { split_in_two: true }),
+        );
+    }
+
+    #[test]
+    fn diary_snapshot() {
+        assert_snapshot(
+            "diary_2024.html",
+            &render_snapshot_calendar(2024, Output::Diary { booklet: false }),
+        );
+    }
+
+    #[test]
+    fn weekly_planner_snapshot() {
+        assert_snapshot(
+            "weekly_planner_2024.html",
+            &render_snapshot_calendar(2024, Output::WeeklyPlanner),
+        );
+    }
+
+    #[test]
+    fn daily_journal_snapshot() {
+        let journal = render_snapshot_calendar(
+            2024,
+            Output::DailyJournal {
+                paper: JournalPaper::Hourly,
+            },
+        );
+
+        // A page per day is too much to review, so only January is kept
+        let (february_start, _) = journal.match_indices("<section>").nth(31).unwrap();
+        let (last_page_end, end_tag) = journal.rmatch_indices("</section>").next().unwrap();
+        let january =
+            journal[..february_start].to_string() + &journal[last_page_end + end_tag.len()..];
+        assert!(january.contains("Wednesday 31 January 2024"));
+        assert!(!january.contains("Thursday 1 February 2024"));
+
+        assert_snapshot("daily_journal_2024_01.html", &january);
+    }
+
+    #[test]
+    fn daily_journal_pages() {
+        let journal = render_snapshot_calendar(
+            2024,
+            Output::DailyJournal {
+                paper: JournalPaper::Hourly,
+            },
+        );
+
+        let pages = journal.split("<section>").skip(1).collect_vec();
+        assert_eq!(pages.len(), 366);
+
+        let page = pages
+            .iter()
+            .find(|page| page.contains("Wednesday 10 January 2024"))
+            .unwrap();
+
+        // Timed events are in the slot of their start hour
+        let (_, club_night_slot) = page
+            .split_once("<span class=\"hour\">19:00</span>")
+            .unwrap();
+        let (club_night_slot, _) = club_night_slot.split_once("</div>").unwrap();
+        assert!(club_night_slot.contains("Club Night"));
+
+        assert!(page.contains("<td class=\"highlighted\">10</td>"));
+
+        // Events in their own slot don't count towards the maximum events of a page
+        let calendar_text = std::iter::once("[Meetings]".to_string())
+            .chain((9..21).map(|hour| format!("7 April {hour}:00 Meeting at {hour}")))
+            .join("\n");
+
+        let journal = render_calendar_text(
+            &calendar_text,
+            2024,
+            Output::DailyJournal {
+                paper: JournalPaper::Hourly,
+            },
+            None,
+        );
+
+        for hour in 9..21 {
+            assert!(journal.contains(&format!("Meeting at {hour}")), "{hour}");
+        }
+
+        assert!(!journal.contains("class=\"event more\""));
+        assert!(!journal.contains("class=\"overflow\""));
+    }
+
+    #[test]
+    fn monthly_mini_months() {
+        let mini_months = |year, month, days_before_start| {
+            let mut cells = std::iter::repeat_with(|| CalendarCell::Empty)
+                .take(days_before_start)
+                .chain(
+                    (1..=days_in_month(year, month)).map(|day| CalendarCell::Day {
+                        day: EventDay { day },
+                        day_events: DayEvents::default(),
+                    }),
+                )
+                .chain(std::iter::repeat_with(|| CalendarCell::Empty))
+                .take(MonthlyCalendar::CELLS_PER_MONTH)
+                .collect_vec();
+
+            MonthlyCalendar::add_mini_months(&mut cells, year, month);
+
+            cells
+                .iter()
+                .enumerate()
+                .filter_map(|(index, cell)| match cell {
+                    CalendarCell::MiniMonth { mini_month } => {
+                        Some((index, mini_month.month, mini_month.year))
+                    }
+                    _ => None,
                 })
-                .collect(),
+                .collect_vec()
+        };
+
+        // January 2025 starts on a Wednesday
+        assert_eq!(
+            mini_months(2025, Month::January, 2),
+            [(1, Month::December, 2024), (39, Month::February, 2025)]
         );
 
-        let output = match self.output {
-            Output::MonthlyCalendar => MonthlyCalendar {
-                calendar_event_styles,
-                events: MONTHS
-                    .iter()
-                    .map(|&month| {
-                        let days_before_start =
-                            chrono::NaiveDate::from_ymd_opt(year, month.number_from_month(), 1)
-                                .unwrap()
-                                .weekday()
-                                .num_days_from_monday() as usize;
+        // December 2025 starts on a Monday
+        assert_eq!(
+            mini_months(2025, Month::December, 0),
+            [(38, Month::November, 2025), (39, Month::January, 2026)]
+        );
+    }
 
-                        std::iter::repeat_with(|| CalendarCell::Empty)
-                            .take(days_before_start)
-                            .chain((1..=days_in_month(year, month)).map(|day| {
-                                CalendarCell::Day {
-                                    day: EventDay { day },
-                                    events: calendar_events
-                                        .remove(&MonthAndDay { month, day })
-                                        .unwrap_or_default(),
-                                }
-                            }))
-                            .chain(std::iter::repeat_with(|| CalendarCell::Empty))
-                            .take(40)
-                            .chain(std::iter::once(CalendarCell::MonthAndYear { month, year }))
-                            .collect()
-                    })
-                    .collect(),
+    #[test]
+    fn diary_booklet_order() {
+        let pages = MONTHS
+            .into_iter()
+            .flat_map(|month| {
+                [
+                    DiaryPage {
+                        month: Some(month),
+                        cells: Vec::new(),
+                    },
+                    DiaryPage {
+                        month: Some(month),
+                        cells: Vec::new(),
+                    },
+                ]
+            })
+            .collect_vec();
+
+        let booklet = Diary::impose_booklet(pages);
+
+        // Six booklet pages of two months each, padded to eight
+        assert_eq!(booklet.len(), 32);
+
+        assert_eq!(
+            booklet
+                .iter()
+                .step_by(Diary::PAGES_PER_BOOKLET_PAGE)
+                .map(|page| page.month)
+                .collect_vec(),
+            [
+                None,
+                Some(Month::January),
+                Some(Month::March),
+                None,
+                Some(Month::November),
+                Some(Month::May),
+                Some(Month::July),
+                Some(Month::September),
+            ]
+        );
+    }
+
+    #[test]
+    fn page_style() {
+        let page_setup = PageSetup {
+            paper_size: PaperSize::A5,
+            margin: 12.5,
+            bleed: 3.0,
+            ..PageSetup::default()
+        };
+
+        assert_eq!(
+            page_setup.page_style(&Output::MonthlyCalendar).to_string(),
+            "<style>@page { size: A5 landscape; margin: 12.5mm; bleed: 3mm; } \
+            @media print { :root { --page-width: 185mm; --page-height: 123mm; } }</style>"
+        );
+
+        assert_eq!(
+            page_setup.page_style(&Output::WeeklyPlanner).to_string(),
+            "<style>@page { size: A5 portrait; margin: 12.5mm; bleed: 3mm; } \
+            @media print { :root { --page-width: 123mm; --page-height: 185mm; } }</style>"
+        );
+
+        assert_eq!(
+            PageSetup {
+                orientation: Orientation::Portrait,
+                ..page_setup
             }
-            .render(),
-            Output::YearlyCalendar { split_in_two } => {
-                let mut months = MONTHS
-                    .iter()
-                    .map(|&month| {
-                        let days_before_start =
-                            chrono::NaiveDate::from_ymd_opt(year, month.number_from_month(), 1)
-                                .unwrap()
-                                .weekday()
-                                .num_days_from_monday() as usize;
+            .page_style(&Output::MonthlyCalendar)
+            .to_string(),
+            "<style>@page { size: A5 portrait; margin: 12.5mm; bleed: 3mm; } \
+            @media print { :root { --page-width: 123mm; --page-height: 185mm; } }</style>"
+        );
 
-                        YearlyCalendarMonth {
-                            month,
-                            days: std::iter::repeat_with(|| YearlyCalendarDay::Empty {
-                                weekday: (),
-                            })
-                            .take(days_before_start)
-                            .chain((1..=days_in_month(year, month)).map(|day| {
-                                YearlyCalendarDay::Day {
-                                    weekday: (),
-                                    day: EventDay { day },
-                                    events: calendar_events
-                                        .remove(&MonthAndDay { month, day })
-                                        .unwrap_or_default(),
-                                }
-                            }))
-                            .chain(std::iter::repeat_with(|| YearlyCalendarDay::Empty {
-                                weekday: (),
-                            }))
-                            .take(YearlyCalendar::ROWS_COUNT)
-                            .zip(weekdays(Weekday::Mon))
-                            .map(|(day, weekday)| day.with_weekday(weekday))
-                            .collect(),
-                        }
-                    })
-                    .collect_vec();
+        assert_eq!(
+            PageSetup {
+                paper_size: PaperSize::Letter,
+                margin: 10.0,
+                ..page_setup
+            }
+            .page_style(&Output::WeeklyPlanner)
+            .to_string(),
+            "<style>@page { size: Letter portrait; margin: 10mm; bleed: 3mm; } \
+            @media print { :root { --page-width: 195.9mm; --page-height: 259.4mm; } }</style>"
+        );
+    }
 
-                YearlyCalendar {
-                    title: if split_in_two { "Half-Year" } else { "Year" },
-                    calendar_event_styles,
-                    year,
-                    weekday_titles: weekdays(Weekday::Mon)
-                        .take(YearlyCalendar::ROWS_COUNT)
-                        .collect(),
-                    pages: if split_in_two {
-                        let latter_months = months.split_off(6);
-                        vec![
-                            YearlyCalendarPage { months },
-                            YearlyCalendarPage {
-                                months: latter_months,
-                            },
-                        ]
-                    } else {
-                        vec![YearlyCalendarPage { months }]
-                    },
+    #[test]
+    fn page_setup_arguments() {
+        let Args {
+            paper_size,
+            orientation,
+            margin,
+            bleed,
+            templates,
+            themes,
+        } = Args::try_parse_from([
+            "diary",
+            "--paper-size",
+            "letter",
+            "--orientation",
+            "landscape",
+            "--margin",
+            "5",
+        ])
+        .unwrap();
+
+        assert_eq!(paper_size, PaperSize::Letter);
+        assert_eq!(orientation, Orientation::Landscape);
+        assert_eq!((margin, bleed), (5.0, 0.0));
+        assert_eq!(templates, None);
+        assert_eq!(themes, None);
+
+        assert!(Args::try_parse_from(["diary", "--paper-size", "B5"]).is_err());
+
+        for (name, value) in [
+            ("--margin", "-1"),
+            ("--margin", "NaN"),
+            ("--margin", "51"),
+            ("--bleed", "inf"),
+            ("--bleed", "10.5"),
+        ] {
+            assert!(
+                Args::try_parse_from(["diary", name, value]).is_err(),
+                "{name} {value}"
+            );
+        }
+
+        let Args { margin, bleed, .. } =
+            Args::try_parse_from(["diary", "--margin", "50", "--bleed", "10"]).unwrap();
+        assert_eq!((margin, bleed), (50.0, 10.0));
+    }
+
+    #[test]
+    fn themes() {
+        let themes = Theme::load_all(None).unwrap();
+
+        assert_eq!(
+            themes
+                .iter()
+                .map(|theme| theme.name.to_string())
+                .collect::<Vec<_>>(),
+            ["Default", "Greyscale", "Elegant"]
+        );
+
+        let theme = Theme::parse(
+            "Test",
+            "weekend-background = \"#e8e8e8\"\nfont-family = \"Georgia, serif\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            ThemeStyle(theme.variables).to_string(),
+            "<style>:root { --font-family: Georgia, serif; --weekend-background: #e8e8e8; }</style>"
+        );
+
+        assert_eq!(ThemeStyle(Default::default()).to_string(), "");
+
+        assert!(Theme::parse("Test", "event-color = 1").is_err());
+        assert!(Theme::parse("Test", "event-color = \"red; }\"").is_err());
+        assert!(Theme::parse("Test", "\"event color\" = \"red\"").is_err());
+        assert!(Theme::parse("Test", "event-color = ").is_err());
+    }
+
+    #[test]
+    fn user_templates() {
+        let templates_directory: std::path::PathBuf =
+            [env!("CARGO_MANIFEST_DIR"), "tests", "templates"]
+                .into_iter()
+                .collect();
+
+        let output = render_snapshot_calendar_with_templates(
+            2024,
+            Output::MonthlyCalendar,
+            Some(templates_directory.clone()),
+        );
+
+        assert!(output.starts_with(super::HTTP_RESPONSE_HEADER));
+        assert!(output.contains("<title>12 months</title>"));
+        assert!(output.contains("@page { size: A4 landscape;"));
+        assert!(output.contains("<p class=\"shadedBackground\">1:"));
+        assert!(output.contains("<span class=\"event eventgroup2\">19:30-22:00 Club Night</span>"));
+
+        // Built-in templates are used when there is no user template
+        assert!(render_snapshot_calendar_with_templates(
+            2024,
+            Output::Diary { booklet: false },
+            Some(templates_directory)
+        )
+        .contains("<title>Diary</title>"));
+    }
+
+    #[test]
+    fn mini_month_weeks() {
+        let mini_month = MiniMonth::new(2024, Month::February);
+
+        assert_eq!(
+            mini_month.weeks,
+            [
+                [None, None, None, Some(1), Some(2), Some(3), Some(4)],
+                [
+                    Some(5),
+                    Some(6),
+                    Some(7),
+                    Some(8),
+                    Some(9),
+                    Some(10),
+                    Some(11)
+                ],
+                [
+                    Some(12),
+                    Some(13),
+                    Some(14),
+                    Some(15),
+                    Some(16),
+                    Some(17),
+                    Some(18)
+                ],
+                [
+                    Some(19),
+                    Some(20),
+                    Some(21),
+                    Some(22),
+                    Some(23),
+                    Some(24),
+                    Some(25)
+                ],
+                [Some(26), Some(27), Some(28), Some(29), None, None, None],
+            ]
+        );
+    }
+
+    #[test]
+    fn days_in_month_matches_calendar() {
+        for year in YEARS {
+            let mut month_lengths = HashMap::<Month, u32>::new();
+
+            for date in days_of_year(year) {
+                *month_lengths.entry(month_of(date)).or_default() += 1;
+            }
+
+            for month in MONTHS {
+                assert_eq!(
+                    days_in_month(year, month),
+                    month_lengths[&month],
+                    "{year}/{}",
+                    month.name()
+                );
+            }
+        }
+
+        assert_eq!(days_in_month(1900, Month::February), 28);
+        assert_eq!(days_in_month(2000, Month::February), 29);
+        assert_eq!(days_in_month(2100, Month::February), 28);
+    }
+
+    #[test]
+    fn fixed_date_matches_brute_force() {
+        for year in YEARS {
+            let days = days_of_year(year);
+
+            for month in MONTHS {
+                for day in 0..=32 {
+                    let expected = days
+                        .iter()
+                        .copied()
+                        .filter(|date| month_of(*date) == month && date.day() == day)
+                        .collect::<Vec<_>>();
+
+                    match fixed_date(month, day).dates(year, &NonWorkingDays::default()) {
+                        Ok(dates) => assert_eq!(dates, expected, "{year}/{}/{day}", month.name()),
+                        Err(_) => assert!(expected.is_empty(), "{year}/{}/{day}", month.name()),
+                    }
                 }
-                .render()
             }
-            Output::Diary => Diary {
-                calendar_event_styles,
-                pages: MONTHS
-                    .iter()
-                    .flat_map(|&month| {
-                        let mut days = (1..=days_in_month(year, month))
-                            .map(|day| DiaryCell::Day {
-                                weekday: chrono::NaiveDate::from_ymd_opt(
-                                    year,
-                                    month.number_from_month(),
-                                    day,
-                                )
-                                .unwrap()
-                                .weekday(),
-                                day: EventDay { day },
-                                events: calendar_events
-                                    .remove(&MonthAndDay { month, day })
-                                    .unwrap_or_default(),
+        }
+    }
+
+    #[test]
+    fn nth_weekday_of_month_matches_brute_force() {
+        for year in YEARS {
+            let mut days_by_month_and_weekday = HashMap::<(Month, Weekday), Vec<NaiveDate>>::new();
+
+            for date in days_of_year(year) {
+                days_by_month_and_weekday
+                    .entry((month_of(date), date.weekday()))
+                    .or_default()
+                    .push(date);
+            }
+
+            for &weekday in Weekday::ALL {
+                for n in -6..=6 {
+                    for month in std::iter::once(None).chain(MONTHS.map(Some)) {
+                        let data = EventDescriptionData::NthWeekdayOfMonth { n, weekday, month };
+
+                        if n == 0 {
+                            assert!(data.dates(year, &NonWorkingDays::default()).is_err());
+                            continue;
+                        }
+
+                        let expected = MONTHS
+                            .into_iter()
+                            .filter(|&candidate| month.is_none_or(|month| month == candidate))
+                            .filter_map(|month| {
+                                let days = &days_by_month_and_weekday[&(month, weekday)];
+
+                                let index = if n > 0 {
+                                    usize::try_from(n - 1).ok()?
+                                } else {
+                                    days.len().checked_sub(usize::try_from(-n).ok()?)?
+                                };
+
+                                days.get(index).copied()
                             })
-                            .chain(std::iter::repeat_with(|| DiaryCell::Empty));
+                            .collect::<Vec<_>>();
 
-                        [
-                            DiaryPage {
-                                month,
-                                cells: days.by_ref().take(16).collect(),
-                            },
-                            DiaryPage {
-                                month,
-                                cells: days.by_ref().take(15).collect(),
-                            },
-                        ]
-                    })
-                    .chunks(8)
-                    .into_iter()
-                    .map(Vec::from_iter)
-                    .collect(),
+                        assert_eq!(
+                            data.dates(year, &NonWorkingDays::default()).unwrap(),
+                            expected,
+                            "{year} {n} {weekday} {month:?}"
+                        );
+                    }
+                }
             }
-            .render(),
         }
-        .context("Failed to render calendar")?;
+    }
 
-        fn worker(output: String) -> anyhow::Result<()> {
-            let listener = std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0))
-                .context("Failed to listen over HTTP")?;
+    #[test]
+    fn day_of_month_matches_brute_force() {
+        for year in YEARS {
+            let mut days_by_month = HashMap::<Month, Vec<NaiveDate>>::new();
 
-            let http_port = listener
-                .local_addr()
-                .context("Failed to get http address")?
-                .port();
+            for date in days_of_year(year) {
+                days_by_month.entry(month_of(date)).or_default().push(date);
+            }
 
-            webbrowser::open(&format!("http://127.0.0.1:{http_port}/"))
-                .context("Failed to open web browser")?;
+            for n in -32..=32 {
+                for month in std::iter::once(None).chain(MONTHS.map(Some)) {
+                    let data = EventDescriptionData::DayOfMonth { n, month };
 
-            let (mut socket, _remote_address) = listener
-                .accept()
-                .context("Failed to accept http connection")?;
+                    if n == 0 {
+                        assert!(data.dates(year, &NonWorkingDays::default()).is_err());
+                        continue;
+                    }
 
-            for line in std::io::BufReader::new(&mut socket).lines() {
-                if line
-                    .context("Failed to read HTTP request")?
-                    .trim()
-                    .is_empty()
-                {
-                    break;
+                    let expected = MONTHS
+                        .into_iter()
+                        .filter(|&candidate| month.is_none_or(|month| month == candidate))
+                        .filter_map(|month| {
+                            let days = &days_by_month[&month];
+
+                            let index = if n > 0 {
+                                usize::try_from(n - 1).ok()?
+                            } else {
+                                days.len().checked_sub(usize::try_from(-n).ok()?)?
+                            };
+
+                            days.get(index).copied()
+                        })
+                        .collect::<Vec<_>>();
+
+                    assert_eq!(
+                        data.dates(year, &NonWorkingDays::default()).unwrap(),
+                        expected,
+                        "{year} {n} {month:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nth_weekday_of_year_matches_brute_force() {
+        for year in YEARS {
+            for &weekday in Weekday::ALL {
+                let days = days_of_year(year)
+                    .into_iter()
+                    .filter(|date| date.weekday() == weekday)
+                    .collect::<Vec<_>>();
+
+                for n in -54..=54 {
+                    let data = EventDescriptionData::NthWeekdayOfYear { n, weekday };
+
+                    if n == 0 {
+                        assert!(data.dates(year, &NonWorkingDays::default()).is_err());
+                        continue;
+                    }
+
+                    let index = if n > 0 {
+                        usize::try_from(n - 1).ok()
+                    } else {
+                        days.len().checked_sub(usize::try_from(-n).unwrap())
+                    };
+
+                    let expected = index
+                        .and_then(|index| days.get(index).copied())
+                        .into_iter()
+                        .collect::<Vec<_>>();
+
+                    assert_eq!(
+                        data.dates(year, &NonWorkingDays::default()).unwrap(),
+                        expected,
+                        "{year} {n} {weekday}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn weekday_of_iso_week_matches_brute_force() {
+        for year in YEARS {
+            let mut days_by_week = HashMap::<u32, Vec<NaiveDate>>::new();
+
+            for date in (year - 1..=year + 1).flat_map(days_of_year) {
+                let iso_week = date.iso_week();
+
+                if iso_week.year() == year {
+                    days_by_week.entry(iso_week.week()).or_default().push(date);
                 }
             }
 
-            socket
-                .write_all(output.as_bytes())
-                .context("Failed to write HTTP response")
-        }
+            let weeks_in_year = u32::try_from(days_by_week.len()).unwrap();
+
+            for &weekday in Weekday::ALL {
+                for week in -54..=54 {
+                    let data = EventDescriptionData::WeekdayOfIsoWeek { week, weekday };
+
+                    if week == 0 {
+                        assert!(data.dates(year, &NonWorkingDays::default()).is_err());
+                        continue;
+                    }
+
+                    let week_number = if week > 0 {
+                        Some(u32::from(week.unsigned_abs()))
+                    } else {
+                        (weeks_in_year + 1).checked_sub(week.unsigned_abs().into())
+                    };
+
+                    let expected = week_number
+                        .and_then(|week_number| days_by_week.get(&week_number))
+                        .and_then(|days| {
+                            days.iter().copied().find(|date| date.weekday() == weekday)
+                        })
+                        .into_iter()
+                        .collect::<Vec<_>>();
 
-        std::thread::spawn(move || {
-            if let Err(err) = worker(output) {
-                if let Err(err) =
-                    events.submit_command(SET_ERROR, ErrorMessage::new(err), druid::Target::Global)
-                {
-                    eprintln!("{err}");
+                    assert_eq!(
+                        data.dates(year, &NonWorkingDays::default()).unwrap(),
+                        expected,
+                        "{year} {week} {weekday}"
+                    );
                 }
             }
-        });
+        }
+    }
 
-        Ok(())
+    #[test]
+    fn iso_weeks_in_long_years() {
+        let calendar = "[Events]\n\
+            53 Thursday/week Week 53\n\
+            -1 Monday/week Last week\n\
+            1 Monday/week First week\n";
+
+        // 2020 and 2026 have 53 ISO weeks, 2025 has 52
+        assert_eq!(
+            group_event_dates(calendar, 2020),
+            vec![
+                ("Week 53".into(), vec![ymd(2020, 12, 31)]),
+                ("Last week".into(), vec![ymd(2020, 12, 28)]),
+                ("First week".into(), vec![ymd(2019, 12, 30)]),
+            ]
+        );
+
+        assert_eq!(
+            group_event_dates(calendar, 2025),
+            vec![
+                ("Week 53".into(), vec![]),
+                ("Last week".into(), vec![ymd(2025, 12, 22)]),
+                ("First week".into(), vec![ymd(2024, 12, 30)]),
+            ]
+        );
+
+        assert_eq!(
+            group_event_dates(calendar, 2026),
+            vec![
+                ("Week 53".into(), vec![ymd(2026, 12, 31)]),
+                ("Last week".into(), vec![ymd(2026, 12, 28)]),
+                ("First week".into(), vec![ymd(2025, 12, 29)]),
+            ]
+        );
     }
-}
 
-macro_rules! create_keys {
-    ($($k:ident : $t:ty),* $(,)?) => {
-        $(
-            const $k: ::druid::Key::<$t> = ::druid::Key::new(concat!(env!("CARGO_PKG_NAME"), ".key.", stringify!($k)));
-        )*
-    };
-}
+    #[test]
+    fn hebrew_dates_match_known_dates() {
+        let calendar = "[Events]\n\
+            15 Nisan Passover\n\
+            1 Tishrei Rosh Hashanah\n\
+            10 Tishrei Yom Kippur\n\
+            25 Kislev Hanukkah\n\
+            10 Tevet Tenth of Tevet\n\
+            14 Adar Purim\n\
+            14 Adar I Purim Katan\n\
+            30 Adar I Rosh Chodesh Adar II\n";
+
+        // 5784 is a leap year, 5785 is not
+        assert_eq!(
+            group_event_dates(calendar, 2024),
+            vec![
+                ("Passover".into(), vec![ymd(2024, 4, 23)]),
+                ("Rosh Hashanah".into(), vec![ymd(2024, 10, 3)]),
+                ("Yom Kippur".into(), vec![ymd(2024, 10, 12)]),
+                ("Hanukkah".into(), vec![ymd(2024, 12, 26)]),
+                ("Tenth of Tevet".into(), vec![]),
+                ("Purim".into(), vec![ymd(2024, 3, 24)]),
+                ("Purim Katan".into(), vec![ymd(2024, 2, 23)]),
+                ("Rosh Chodesh Adar II".into(), vec![ymd(2024, 3, 10)]),
+            ]
+        );
 
-create_keys!(
-    WIDGET_PADDING_INSETS: druid::Insets,
-    MARKDOWN_LIST_PADDING: f64,
-    EVENT_GROUP_TITLE: ArcStr,
-);
+        assert_eq!(
+            group_event_dates(calendar, 2025),
+            vec![
+                ("Passover".into(), vec![ymd(2025, 4, 13)]),
+                ("Rosh Hashanah".into(), vec![ymd(2025, 9, 23)]),
+                ("Yom Kippur".into(), vec![ymd(2025, 10, 2)]),
+                ("Hanukkah".into(), vec![ymd(2025, 12, 15)]),
+                (
+                    "Tenth of Tevet".into(),
+                    vec![ymd(2025, 1, 10), ymd(2025, 12, 30)]
+                ),
+                ("Purim".into(), vec![ymd(2025, 3, 14)]),
+                ("Purim Katan".into(), vec![ymd(2025, 3, 14)]),
+                ("Rosh Chodesh Adar II".into(), vec![]),
+            ]
+        );
+    }
 
-macro_rules! create_selectors {
-    ($($k:ident : $t:ty),* $(,)?) => {
-        $(
-            const $k: ::druid::Selector::<$t> = ::druid::Selector::new(concat!(env!("CARGO_PKG_NAME"), ".selector.", stringify!($k)));
-        )*
-    };
-}
+    #[test]
+    fn hebrew_years_are_contiguous() {
+        for year in YEARS.map(|year| i64::from(year) + 3760) {
+            let hebrew_year = HebrewYear(year);
 
-create_selectors!(
-    SET_ERROR: ErrorMessage,
-    SHOW_HELP: (),
-    OPEN_LINK: String,
-);
+            assert!(
+                [353, 354, 355, 383, 384, 385].contains(&hebrew_year.days()),
+                "{year}"
+            );
 
-struct AppController;
+            assert_eq!(hebrew_year.days() > 380, hebrew_year.is_leap(), "{year}");
 
-impl AppController {
-    fn cache_path() -> Option<std::path::PathBuf> {
-        let project_directories = directories::ProjectDirs::from("", "", "calendargenerator")?;
-        let config_directory = project_directories.config_local_dir();
+            let dates = hebrew_year
+                .months()
+                .flat_map(|month| (1..=30).filter_map(move |day| HebrewYear(year).date(month, day)))
+                .collect::<Vec<_>>();
 
-        std::fs::create_dir_all(config_directory)
-            .context("Failed to create config directory")
-            .map_err(|err| eprintln!("{err:?}"))
-            .ok();
+            let new_year = dates[0];
 
-        Some(
-            [config_directory, "calendar_file_path.txt".as_ref()]
-                .into_iter()
-                .collect(),
-        )
-    }
+            assert!(
+                ![Weekday::Sun, Weekday::Wed, Weekday::Fri].contains(&new_year.weekday()),
+                "{year} {new_year}"
+            );
 
-    fn open_calendar_dialog() -> druid::Command {
-        druid::commands::SHOW_OPEN_PANEL.with(
-            druid::FileDialogOptions::new()
-                .allowed_types(vec![druid::FileSpec::new("Calendar", &["txt"])])
-                .title("Open a calendar"),
-        )
-    }
+            assert_eq!(
+                dates,
+                new_year
+                    .iter_days()
+                    .take(hebrew_year.days().try_into().unwrap())
+                    .collect::<Vec<_>>(),
+                "{year}"
+            );
 
-    fn help() -> impl Widget<AppState> {
-        Self::help_blocks(markdown::tokenize(include_str!("../README.md"))).scroll()
+            assert_eq!(
+                HebrewYear(year + 1).date(7, 1),
+                dates.last().unwrap().succ_opt(),
+                "{year}"
+            );
+        }
     }
 
-    fn help_blocks(blocks: Vec<markdown::Block>) -> impl Widget<AppState> {
-        blocks.into_iter().fold(
-            Flex::column().cross_axis_alignment(druid::widget::CrossAxisAlignment::Start),
-            |column, block| match block {
-                markdown::Block::Header(spans, level) => column
-                    .with_spacer(match level {
-                        1 => 0.0,
-                        2 => 19.92,
-                        3 => 18.72,
-                        4 => 21.28,
-                        5 => 22.1776,
-                        6 => 24.9776,
-                        _ => 0.0,
+    #[test]
+    fn hebrew_dates_cover_every_day() {
+        for year in YEARS.step_by(10) {
+            let dates = HebrewMonth::NAMES
+                .into_iter()
+                .flat_map(|(_, month)| {
+                    (1..=30).flat_map(move |day| {
+                        EventDescriptionData::HebrewDate { month, day }
+                            .dates(year, &NonWorkingDays::default())
+                            .unwrap()
                     })
-                    .with_child(Self::help_spans_with_modify_text(spans, move |text| {
-                        text.size(match level {
-                            1 => 32.0,
-                            2 => 24.0,
-                            3 => 18.72,
-                            4 => 16.0,
-                            5 => 13.28,
-                            6 => 10.72,
-                            _ => 16.0,
-                        });
+                })
+                .collect::<std::collections::BTreeSet<_>>();
 
-                        text.text_color(druid::Color::rgb8(0x56, 0x9c, 0xd6));
+            assert_eq!(
+                dates.into_iter().collect::<Vec<_>>(),
+                days_of_year(year),
+                "{year}"
+            );
+        }
+    }
 
-                        text.underline(true);
-                    }))
-                    .with_spacer(match level {
-                        1 => 21.44,
-                        2 => 19.92,
-                        3 => 18.72,
-                        4 => 21.28,
-                        5 => 22.1776,
-                        6 => 24.9776,
-                        _ => 0.0,
-                    }),
-                markdown::Block::Paragraph(spans) => column.with_child(Self::help_spans(spans)),
-                markdown::Block::Blockquote(_) => unimplemented!(),
-                markdown::Block::CodeBlock(_, _) => unimplemented!(),
-                markdown::Block::OrderedList(_, _) => unimplemented!(),
-                markdown::Block::UnorderedList(list) => column.with_child(
-                    list.into_iter()
-                        .map(|item| {
-                            let row = Flex::row()
-                                .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start)
-                                .with_spacer(MARKDOWN_LIST_PADDING)
-                                .with_child(Label::new("•"));
+    #[test]
+    fn hijri_dates_match_known_dates() {
+        let calendar = "[Events]\n\
+            1 Ramadan Ramadan begins\n\
+            1 Shawwal Eid al-Fitr\n\
+            1 day before 10 Dhu al-Hijjah Day of Arafah\n\
+            10 Dhu al-Hijjah Eid al-Adha\n\
+            1 Muharram Islamic New Year\n";
+
+        assert_eq!(
+            group_event_dates(calendar, 2024),
+            vec![
+                ("Ramadan begins".into(), vec![ymd(2024, 3, 11)]),
+                ("Eid al-Fitr".into(), vec![ymd(2024, 4, 10)]),
+                ("Day of Arafah".into(), vec![ymd(2024, 6, 16)]),
+                ("Eid al-Adha".into(), vec![ymd(2024, 6, 17)]),
+                // A day after it was observed, as 1445 is a leap year in the tabular calendar
+                ("Islamic New Year".into(), vec![ymd(2024, 7, 8)]),
+            ]
+        );
 
-                            match item {
-                                markdown::ListItem::Simple(spans) => {
-                                    row.with_child(Self::help_spans(spans))
-                                }
-                                markdown::ListItem::Paragraph(blocks) => {
-                                    row.with_child(Self::help_blocks(blocks))
-                                }
-                            }
-                        })
-                        .fold(
-                            Flex::column()
-                                .cross_axis_alignment(druid::widget::CrossAxisAlignment::Start),
-                            Flex::with_child,
-                        ),
-                ),
-                markdown::Block::Raw(_) => unimplemented!(),
-                markdown::Block::Hr => unimplemented!(),
-            },
-        )
+        // Eid al-Fitr falls at both ends of 2000
+        assert_eq!(
+            group_event_dates(calendar, 2000)[1],
+            (
+                "Eid al-Fitr".into(),
+                vec![ymd(2000, 1, 8), ymd(2000, 12, 28)]
+            )
+        );
     }
 
-    fn apply_help_spans(
-        rows: &mut Vec<druid::text::RichTextBuilder>,
-        modify_text: &dyn Fn(&mut druid::text::AttributesAdder),
-        spans: Vec<markdown::Span>,
-    ) {
-        for span in spans {
-            let mut text_attributes = match span {
-                markdown::Span::Break => {
-                    rows.push(druid::text::RichTextBuilder::new());
-                    continue;
-                }
-                markdown::Span::Text(text) => rows.last_mut().unwrap().push(&text),
-                markdown::Span::Code(text) => {
-                    let mut text_attributes = rows.last_mut().unwrap().push(&text);
+    #[test]
+    fn hijri_years_are_contiguous() {
+        for year in 1000..=1900 {
+            let hijri_year = HijriYear(year);
+
+            let dates = (1..=12)
+                .flat_map(|month| (1..=30).filter_map(move |day| HijriYear(year).date(month, day)))
+                .collect::<Vec<_>>();
+
+            assert_eq!(
+                dates.len(),
+                if hijri_year.is_leap() { 355 } else { 354 },
+                "{year}"
+            );
 
-                    text_attributes
-                        .font_family(druid::FontFamily::MONOSPACE)
-                        .text_color(druid::Color::rgb8(0xce, 0x91, 0x78));
+            assert_eq!(
+                dates,
+                dates[0].iter_days().take(dates.len()).collect::<Vec<_>>(),
+                "{year}"
+            );
 
-                    text_attributes
-                }
-                markdown::Span::Link(text, url, _title) => {
-                    let mut text_attributes = rows.last_mut().unwrap().push(&text);
+            assert_eq!(
+                HijriYear(year + 1).date(1, 1),
+                dates.last().unwrap().succ_opt(),
+                "{year}"
+            );
+        }
+    }
 
-                    text_attributes.link(OPEN_LINK.with(url)).underline(true);
+    #[test]
+    fn hijri_dates_cover_every_day() {
+        for year in YEARS.step_by(10) {
+            let mut dates = Vec::new();
 
-                    text_attributes
-                }
-                markdown::Span::Image(_, _, _) => unimplemented!(),
-                markdown::Span::Emphasis(spans) => {
-                    Self::apply_help_spans(
-                        rows,
-                        &|text| {
-                            modify_text(text);
-                            text.style(druid::FontStyle::Italic);
-                        },
-                        spans,
-                    );
+            for month in 1..=12 {
+                for day in 1..=30 {
+                    let data = EventDescriptionData::HijriDate { month, day };
+                    let day_dates = data.dates(year, &NonWorkingDays::default()).unwrap();
 
-                    continue;
-                }
-                markdown::Span::Strong(spans) => {
-                    Self::apply_help_spans(
-                        rows,
-                        &|text| {
-                            modify_text(text);
-                            text.weight(druid::FontWeight::BOLD);
-                        },
-                        spans,
+                    // Even months have 29 days, apart from Dhu al-Hijjah in leap years
+                    let min_dates = usize::from(day < 30 || month % 2 == 1);
+
+                    assert!(
+                        (min_dates..=2).contains(&day_dates.len()),
+                        "{year} {month} {day}"
                     );
 
-                    continue;
+                    dates.extend(day_dates);
                 }
-            };
+            }
 
-            modify_text(&mut text_attributes);
+            dates.sort();
+
+            assert_eq!(dates, days_of_year(year), "{year}");
         }
     }
 
-    fn help_spans(spans: Vec<markdown::Span>) -> impl Widget<AppState> {
-        Self::help_spans_with_modify_text(spans, |_| {})
-    }
+    #[test]
+    fn chinese_dates_match_known_dates() {
+        let calendar = "[Events]\n\
+            day before 1 lunar/1 Lunar New Year's Eve\n\
+            1 lunar/1 Lunar New Year\n\
+            1 lunar/3 Third month\n\
+            5 lunar/5 Dragon Boat Festival\n\
+            15 lunar/8 Mid-Autumn Festival\n";
+
+        assert_eq!(
+            group_event_dates(calendar, 2024),
+            vec![
+                ("Lunar New Year's Eve".into(), vec![ymd(2024, 2, 9)]),
+                ("Lunar New Year".into(), vec![ymd(2024, 2, 10)]),
+                ("Third month".into(), vec![ymd(2024, 4, 9)]),
+                ("Dragon Boat Festival".into(), vec![ymd(2024, 6, 10)]),
+                ("Mid-Autumn Festival".into(), vec![ymd(2024, 9, 17)]),
+            ]
+        );
 
-    fn help_spans_with_modify_text(
-        spans: Vec<markdown::Span>,
-        modify_text: impl Fn(&mut druid::text::AttributesAdder),
-    ) -> impl Widget<AppState> {
-        let mut rows = vec![druid::text::RichTextBuilder::new()];
+        // 2023 has a leap month after the 2nd month
+        assert_eq!(
+            group_event_dates(calendar, 2023),
+            vec![
+                ("Lunar New Year's Eve".into(), vec![ymd(2023, 1, 21)]),
+                ("Lunar New Year".into(), vec![ymd(2023, 1, 22)]),
+                ("Third month".into(), vec![ymd(2023, 4, 20)]),
+                ("Dragon Boat Festival".into(), vec![ymd(2023, 6, 22)]),
+                ("Mid-Autumn Festival".into(), vec![ymd(2023, 9, 29)]),
+            ]
+        );
 
-        Self::apply_help_spans(&mut rows, &modify_text, spans);
+        let lunar_new_year = EventDescriptionData::ChineseDate { month: 1, day: 1 };
+
+        for (year, date) in [
+            (1900, ymd(1900, 1, 31)),
+            (1949, ymd(1949, 1, 29)),
+            (2000, ymd(2000, 2, 5)),
+            (2025, ymd(2025, 1, 29)),
+            (2100, ymd(2100, 2, 9)),
+        ] {
+            assert_eq!(
+                lunar_new_year
+                    .dates(year, &NonWorkingDays::default())
+                    .unwrap(),
+                vec![date]
+            );
+        }
 
-        rows.into_iter()
-            .map(|text| Label::raw().lens(druid::lens::Constant(text.build())))
-            .fold(Flex::column(), Flex::with_child)
+        for year in [1898, 2101] {
+            assert_eq!(
+                lunar_new_year
+                    .dates(year, &NonWorkingDays::default())
+                    .unwrap(),
+                vec![]
+            );
+        }
+
+        // Rendering also uses the neighbouring years, which are outside the table
+        for year in [1900, 2100] {
+            assert!(
+                render_snapshot_calendar(year, Output::MonthlyCalendar).contains("Lunar New Year")
+            );
+        }
     }
 
-    fn parse_calendar(
-        calendar_file: &std::path::Path,
-    ) -> anyhow::Result<Vector<EventGroupDescription>> {
-        let calendar_text = std::fs::read_to_string(calendar_file)
-            .with_context(|| format!("Failed to read {}", calendar_file.display()))?;
+    #[test]
+    fn chinese_years_are_valid() {
+        for year in 1899..=2100 {
+            let chinese_year = ChineseYear(year);
+            let days = chinese_year.days().unwrap();
+            let new_year = chinese_year.new_year().unwrap();
+
+            assert!([353, 354, 355, 383, 384, 385].contains(&days), "{year}");
+
+            assert_eq!(
+                chinese_year
+                    .months()
+                    .unwrap()
+                    .any(|(_, is_leap_month, _)| is_leap_month),
+                days > 380,
+                "{year}"
+            );
 
-        let mut event_group_descriptions = Vec::<EventGroupDescription>::new();
+            assert!(
+                (ymd(year, 1, 21)..=ymd(year, 2, 20)).contains(&new_year),
+                "{year} {new_year}"
+            );
+        }
 
-        for (line_num, line) in calendar_text.lines().enumerate() {
-            let line_num = line_num + 1;
+        // The year after the table ends starts on 29 January 2101
+        let last_year = ChineseYear(2100);
 
-            let line = line.trim();
+        assert_eq!(
+            last_year.new_year().unwrap() + chrono::Duration::days(last_year.days().unwrap()),
+            ymd(2101, 1, 29)
+        );
+    }
+
+    #[test]
+    fn days_after_easter_is_offset_from_easter_sunday() {
+        for year in YEARS {
+            let [easter] = EventDescriptionData::DaysAfterEaster { day_offset: 0 }
+                .dates(year, &NonWorkingDays::default())
+                .unwrap()[..]
+            else {
+                panic!("Expected a single Easter in {year}");
+            };
+
+            assert_eq!(easter.weekday(), Weekday::Sun, "{year}");
+            assert!(
+                (NaiveDate::from_ymd_opt(year, 3, 22).unwrap()
+                    ..=NaiveDate::from_ymd_opt(year, 4, 25).unwrap())
+                    .contains(&easter),
+                "{easter}"
+            );
 
-            if line.is_empty() {
-                continue;
+            for day_offset in [-47, -2, 1, 39, 49] {
+                assert_eq!(
+                    EventDescriptionData::DaysAfterEaster { day_offset }
+                        .dates(year, &NonWorkingDays::default())
+                        .unwrap(),
+                    vec![easter + chrono::Duration::days(day_offset.into())],
+                    "{year} {day_offset}"
+                );
             }
+        }
+    }
 
-            if let Some(line) = line.strip_prefix('[') {
-                let Some(title_and_style) = line.strip_suffix(']') else {
-                    anyhow::bail!(
-                        "Error on line {line_num}: Event Group titles must end with a ']'"
-                    );
+    #[test]
+    fn fuzzy_sunday_matches_brute_force() {
+        for year in YEARS {
+            for date in days_of_year(year) {
+                let expected = if matches!(date.weekday(), Weekday::Sat | Weekday::Mon) {
+                    date.pred_opt()
+                        .into_iter()
+                        .chain(date.succ_opt())
+                        .filter(|candidate| candidate.weekday() == Weekday::Sun)
+                        .collect::<Vec<_>>()
+                } else {
+                    vec![date]
                 };
 
-                let (title, style) = title_and_style
-                    .split_once(':')
-                    .map(|(title, style)| (title.trim(), Some(style.into())))
-                    .unwrap_or((title_and_style, None));
-                let id = GroupId::Group(event_group_descriptions.len());
-
-                event_group_descriptions.push(EventGroupDescription {
-                    id,
-                    title: title.trim().into(),
-                    style,
-                    events: Vector::new(),
-                    is_selected: false,
-                });
-            } else {
-                let Some(current_group) = event_group_descriptions.last_mut() else {
-                    anyhow::bail!("Calendar must start with an event group");
-                };
+                let data = EventDescriptionData::FuzzySunday(Box::new(fixed_date(
+                    month_of(date),
+                    date.day(),
+                )));
 
-                current_group
-                    .events
-                    .push_back(EventDescription::parse(line, current_group.id)?)
+                assert_eq!(
+                    data.dates(year, &NonWorkingDays::default()).unwrap(),
+                    expected,
+                    "{date}"
+                );
             }
         }
-
-        Ok(event_group_descriptions.into())
     }
-}
 
-impl<W: Widget<AppState>> druid::widget::Controller<AppState, W> for AppController {
-    fn event(
-        &mut self,
-        child: &mut W,
-        ctx: &mut EventCtx,
-        event: &druid::Event,
-        data: &mut AppState,
-        env: &Env,
-    ) {
-        if let druid::Event::WindowConnected = event {
-            if let Some(events) = Self::cache_path().and_then(|cache_path| {
-                let path = std::fs::read_to_string(cache_path).ok()?;
+    #[test]
+    fn weekday_substitution_matches_brute_force() {
+        for year in YEARS {
+            for date in days_of_year(year) {
+                let base = || Box::new(fixed_date(month_of(date), date.day()));
 
-                AppController::parse_calendar(path.trim().as_ref()).ok()
-            }) {
-                data.event_group_descriptions = events;
-            } else {
-                ctx.submit_command(Self::open_calendar_dialog());
+                let next_weekday = date
+                    .iter_days()
+                    .find(|candidate| !candidate.weekday().is_weekend())
+                    .unwrap();
+
+                let nearest_weekday = [0, -1, 1]
+                    .into_iter()
+                    .map(|offset| date + chrono::Duration::days(offset))
+                    .find(|candidate| !candidate.weekday().is_weekend())
+                    .unwrap();
+
+                assert_eq!(
+                    EventDescriptionData::NextWeekday(base())
+                        .dates(year, &NonWorkingDays::default())
+                        .unwrap(),
+                    vec![next_weekday],
+                    "next weekday {date}"
+                );
+
+                assert_eq!(
+                    EventDescriptionData::Substitute(base())
+                        .dates(year, &NonWorkingDays::default())
+                        .unwrap(),
+                    vec![next_weekday],
+                    "substitute {date}"
+                );
+
+                assert_eq!(
+                    EventDescriptionData::NearestWeekday(base())
+                        .dates(year, &NonWorkingDays::default())
+                        .unwrap(),
+                    vec![nearest_weekday],
+                    "nearest weekday {date}"
+                );
             }
         }
+    }
 
-        child.event(ctx, event, data, env)
+    #[test]
+    fn substitute_days_do_not_collide() {
+        let calendar = "[Bank Holidays]\n\
+            substitute 25 December Christmas Day\n\
+            substitute 26 December Boxing Day\n";
+
+        for (year, christmas, boxing_day) in [
+            (2020, 25, 28),
+            (2021, 27, 28),
+            (2022, 27, 26),
+            (2023, 25, 26),
+            (2026, 25, 28),
+            (2027, 27, 28),
+            (2032, 27, 28),
+        ] {
+            assert_eq!(
+                group_event_dates(calendar, year),
+                vec![
+                    ("Christmas Day".into(), vec![ymd(year, 12, christmas)]),
+                    ("Boxing Day".into(), vec![ymd(year, 12, boxing_day)]),
+                ],
+                "{year}"
+            );
+        }
     }
-}
 
-impl druid::AppDelegate<AppState> for AppController {
-    fn command(
-        &mut self,
-        ctx: &mut druid::DelegateCtx,
-        _target: druid::Target,
-        command: &druid::Command,
-        data: &mut AppState,
-        _env: &Env,
-    ) -> druid::Handled {
-        if let Some(error) = command.get(SET_ERROR) {
-            data.error_message = Some(error.clone());
+    #[test]
+    fn substitute_days_skip_other_events_in_group() {
+        let calendar = "[Holidays]\n\
+            substitute 4 July Independence Day\n\
+            1 Monday/July Staff Day\n";
+
+        // 4 July 2027 is a Sunday, and the following Monday is taken
+        assert_eq!(
+            group_event_dates(calendar, 2027),
+            vec![
+                ("Independence Day".into(), vec![ymd(2027, 7, 6)]),
+                ("Staff Day".into(), vec![ymd(2027, 7, 5)]),
+            ]
+        );
 
-            druid::Handled::Yes
-        } else if let Some(()) = command.get(SHOW_HELP) {
-            ctx.new_window::<AppState>(
-                druid::WindowDesc::new(AppController::help().controller(AppController))
-                    .title("Help"),
-            );
+        // Events in other groups don't block substitution
+        assert_eq!(
+            group_event_dates(
+                "[Holidays]\n\
+                substitute 4 July Independence Day\n\
+                [Other]\n\
+                1 Monday/July Staff Day\n",
+                2027
+            ),
+            vec![("Independence Day".into(), vec![ymd(2027, 7, 5)])]
+        );
+    }
 
-            druid::Handled::Yes
-        } else if let Some(url) = command.get(OPEN_LINK) {
-            if let Err(err) = webbrowser::open(url).context("Failed to open browser") {
-                data.error_message = Some(ErrorMessage::new(err));
+    #[test]
+    fn nth_weekday_relative_to_matches_brute_force() {
+        for year in YEARS.step_by(50) {
+            for date in days_of_year(year) {
+                for relation in DateRelation::ALL {
+                    let candidates = relation_candidates(date, relation);
+
+                    for &weekday in Weekday::ALL {
+                        for n in 1..=5 {
+                            let expected = candidates
+                                .iter()
+                                .copied()
+                                .filter(|candidate| candidate.weekday() == weekday)
+                                .nth(usize::from(n) - 1)
+                                .unwrap();
+
+                            let data = EventDescriptionData::NthWeekdayRelativeTo {
+                                n,
+                                weekday,
+                                relation,
+                                base: Box::new(fixed_date(month_of(date), date.day())),
+                            };
+
+                            assert_eq!(
+                                data.dates(year, &NonWorkingDays::default()).unwrap(),
+                                vec![expected],
+                                "{n} {weekday} {} {date}",
+                                relation.name()
+                            );
+                        }
+                    }
+                }
             }
+        }
+    }
 
-            druid::Handled::Yes
-        } else if let Some(calendar_file) = command.get(druid::commands::OPEN_FILE) {
-            if let Err(err) =
-                Self::parse_calendar(calendar_file.path()).and_then(|event_group_descriptions| {
-                    data.event_group_descriptions = event_group_descriptions;
+    fn test_non_working_days() -> NonWorkingDays {
+        // An irregular pattern, including runs of consecutive days
+        NonWorkingDays(
+            (1550..=2450)
+                .flat_map(days_of_year)
+                .filter(|date| date.ordinal() % 11 == 0 || date.ordinal() % 17 < 2)
+                .collect(),
+        )
+    }
 
-                    if let Some(cache_path) = Self::cache_path() {
-                        std::fs::write(
-                            cache_path,
-                            calendar_file.path().as_os_str().as_encoded_bytes(),
-                        )
-                        .context("Failed to write cached calendar path")?;
+    #[test]
+    fn nth_workday_of_month_matches_brute_force() {
+        let non_working_days = test_non_working_days();
+
+        for year in YEARS {
+            let mut workdays_by_month = HashMap::<Month, Vec<NaiveDate>>::new();
+
+            for date in days_of_year(year) {
+                if !date.weekday().is_weekend() && !non_working_days.0.contains(&date) {
+                    workdays_by_month
+                        .entry(month_of(date))
+                        .or_default()
+                        .push(date);
+                }
+            }
+
+            for n in [-25, -23, -22, -2, -1, 0, 1, 2, 22, 23, 25] {
+                for month in std::iter::once(None).chain(MONTHS.map(Some)) {
+                    let data = EventDescriptionData::NthWorkdayOfMonth { n, month };
+
+                    if n == 0 {
+                        assert!(data.dates(year, &non_working_days).is_err());
+                        continue;
                     }
 
-                    Ok(())
-                })
-            {
-                data.error_message = Some(ErrorMessage::new(err));
-            }
+                    let expected = MONTHS
+                        .into_iter()
+                        .filter(|&candidate| month.is_none_or(|month| month == candidate))
+                        .filter_map(|month| {
+                            let days = workdays_by_month.get(&month)?;
 
-            druid::Handled::Yes
-        } else {
-            druid::Handled::No
+                            let index = if n > 0 {
+                                usize::try_from(n - 1).ok()?
+                            } else {
+                                days.len().checked_sub(usize::try_from(-n).ok()?)?
+                            };
+
+                            days.get(index).copied()
+                        })
+                        .collect::<Vec<_>>();
+
+                    assert_eq!(
+                        data.dates(year, &non_working_days).unwrap(),
+                        expected,
+                        "{year} {n} workday {month:?}"
+                    );
+                }
+            }
         }
     }
-}
 
-fn app_view() -> impl Widget<AppState> {
-    Flex::column()
-        .with_child(
-            druid::widget::Maybe::or_empty(|| {
-                Flex::column()
-                    .with_child(
-                        Flex::column()
-                            .with_child(Label::new("Error!"))
-                            .with_default_spacer()
-                            .with_child(Label::raw())
-                            .border(
-                                druid::theme::BORDER_DARK,
-                                druid::theme::TEXTBOX_BORDER_WIDTH,
-                            )
-                            .expand_width(),
-                    )
-                    .with_default_spacer()
-                    .expand_width()
-            })
-            .lens(AppState::error_message),
-        )
-        .with_child(
-            Flex::column()
-                .with_child(Label::new("Year"))
-                .with_default_spacer()
-                .with_child(
-                    Flex::row()
-                        .with_child(Label::dynamic(|data, _env| format!("{data}")))
-                        .with_child(Stepper::new()),
-                )
-                .with_default_spacer()
-                .border(
-                    druid::theme::BORDER_DARK,
-                    druid::theme::TEXTBOX_BORDER_WIDTH,
-                )
-                .expand_width()
-                .lens(AppState::year.then(druid::lens::Map::new(
-                    |&year: &i32| year.into(),
-                    |current_year, new_year: f64| *current_year = new_year as i32,
-                ))),
-        )
-        .with_default_spacer()
-        .with_child(
-            Flex::column()
-                .with_child(Label::new("Calendar Type"))
-                .with_default_spacer()
-                .with_child(RadioGroup::column([
-                    ("Month", Output::MonthlyCalendar),
-                    (
-                        "Year",
-                        Output::YearlyCalendar {
-                            split_in_two: false,
-                        },
-                    ),
-                    ("Half-Year", Output::YearlyCalendar { split_in_two: true }),
-                    ("Diary", Output::Diary),
-                ]))
-                .with_default_spacer()
-                .border(
-                    druid::theme::BORDER_DARK,
-                    druid::theme::TEXTBOX_BORDER_WIDTH,
-                )
-                .expand_width()
-                .lens(AppState::output),
-        )
-        .with_default_spacer()
-        .with_flex_child(
-            Flex::column()
-                .with_child(Label::new("Include Event Groups"))
-                .with_default_spacer()
-                .with_flex_child(
-                    List::new(|| {
-                        Checkbox::new(|_data: &bool, env: &Env| env.get(EVENT_GROUP_TITLE))
-                            .lens(EventGroupDescription::is_selected)
-                            .env_scope(|env: &mut Env, data: &EventGroupDescription| {
-                                env.set(EVENT_GROUP_TITLE, data.title.clone())
+    #[test]
+    fn nth_workday_relative_to_matches_brute_force() {
+        let non_working_days = test_non_working_days();
+
+        for year in YEARS.step_by(50) {
+            for date in days_of_year(year) {
+                for relation in DateRelation::ALL {
+                    let candidates = relation_candidates(date, relation);
+
+                    for n in 1..=6 {
+                        let expected = candidates
+                            .iter()
+                            .copied()
+                            .filter(|candidate| {
+                                !candidate.weekday().is_weekend()
+                                    && !non_working_days.0.contains(candidate)
                             })
-                    })
-                    .align_horizontal(druid::UnitPoint::CENTER)
-                    .scroll()
-                    .vertical()
-                    .lens(AppState::event_group_descriptions),
-                    1.0,
-                )
-                .border(
-                    druid::theme::BORDER_DARK,
-                    druid::theme::TEXTBOX_BORDER_WIDTH,
-                )
-                .expand(),
-            1.0,
-        )
-        .with_default_spacer()
-        .with_child(
-            Button::new("Create").on_click(|ctx, data: &mut AppState, _| {
-                if let Err(err) = data.show_calendar(ctx.get_external_handle()) {
-                    data.error_message = Some(ErrorMessage::new(err));
+                            .nth(usize::from(n) - 1)
+                            .unwrap();
+
+                        let data = EventDescriptionData::NthWorkdayRelativeTo {
+                            n,
+                            relation,
+                            base: Box::new(fixed_date(month_of(date), date.day())),
+                        };
+
+                        assert_eq!(
+                            data.dates(year, &non_working_days).unwrap(),
+                            vec![expected],
+                            "{n} workdays {} {date}",
+                            relation.name()
+                        );
+                    }
                 }
-            }),
-        )
-        .padding(WIDGET_PADDING_INSETS)
-}
+            }
+        }
+    }
 
-fn main() -> anyhow::Result<()> {
-    let app_name = "Create Calendar";
+    #[test]
+    fn max_events_setting() {
+        let mut app_state = AppState {
+            error_message: None,
+            year: 2024,
+            output: Output::MonthlyCalendar,
+            max_events: MaxEvents::default(),
+            page_setup: PageSetup::default(),
+            templates_directory: None,
+            themes: Default::default(),
+            theme_index: 0,
+            event_group_descriptions: AppController::parse_calendar_text(
+                "[Birthdays]\n7 April Alice\n7 April Bob\n7 April Carol\n",
+            )
+            .unwrap(),
+        };
 
-    druid::AppLauncher::with_window(
-        druid::WindowDesc::new(app_view().controller(AppController))
-            .title(app_name)
-            .menu(move |_, _, _| {
-                druid::Menu::new(app_name)
-                    .entry(
-                        druid::MenuItem::new("Open Calendar")
-                            .command(AppController::open_calendar_dialog()),
-                    )
-                    .separator()
-                    .entry(druid::MenuItem::new("Help").command(SHOW_HELP))
-            })
-            .window_size(Size::new(800.0, 600.0)),
-    )
-    .delegate(AppController)
-    .configure_env(|env, _| {
-        let padding_horizontal = env.get(druid::theme::WIDGET_PADDING_HORIZONTAL);
-        let padding_vertical = env.get(druid::theme::WIDGET_PADDING_VERTICAL);
+        app_state.event_group_descriptions[0].is_selected = true;
 
-        env.set(
-            WIDGET_PADDING_INSETS,
-            druid::Insets::uniform_xy(padding_horizontal, padding_vertical),
-        );
+        assert!(!app_state.render_calendar().unwrap().contains("more</span>"));
 
-        env.set(MARKDOWN_LIST_PADDING, 2.0 * padding_horizontal)
-    })
-    .launch(AppState {
-        error_message: None,
-        year: chrono::Local::now().year(),
-        output: Output::MonthlyCalendar,
-        event_group_descriptions: Vector::new(),
-    })?;
+        *app_state.max_events.get_mut(&Output::MonthlyCalendar) = 2;
 
-    Ok(())
-}
+        // The setting of each calendar type is separate
+        assert_eq!(app_state.max_events.get(&Output::WeeklyPlanner), 8);
+
+        let calendar = app_state.render_calendar().unwrap();
+        assert!(calendar.contains("Alice"));
+        assert!(calendar.contains("+2 more"));
+    }
 
-#[cfg(test)]
-mod tests {
     #[test]
-    fn create_help() {
-        super::AppController::help();
+    fn fit_keeps_events_by_priority_and_group() {
+        let events = || {
+            [("a", 2), ("b", 0), ("c", 1), ("d", 0)]
+                .into_iter()
+                .map(|(title, group)| EventWithGroupId {
+                    title: title.into(),
+                    group_id: GroupId::Group(group),
+                    ..EventWithGroupId::default()
+                })
+                .collect_vec()
+        };
+
+        let titles = |events: Vec<EventWithGroupId>| {
+            events
+                .into_iter()
+                .map(|event| event.title.to_string())
+                .collect_vec()
+        };
+
+        let (shown, hidden) = EventWithGroupId::fit(events(), 4);
+        assert_eq!(titles(shown), ["a", "b", "c", "d"]);
+        assert!(hidden.is_empty());
+
+        let (shown, hidden) = EventWithGroupId::fit(events(), 3);
+        assert_eq!(titles(shown), ["b", "d"]);
+        assert_eq!(titles(hidden), ["a", "c"]);
+
+        // A higher priority wins over an earlier group
+        let mut prioritised_events = events();
+        prioritised_events[2].priority = 1;
+
+        let (shown, hidden) = EventWithGroupId::fit(prioritised_events, 3);
+        assert_eq!(titles(shown), ["b", "c"]);
+        assert_eq!(titles(hidden), ["a", "d"]);
+
+        let (shown, hidden) = EventWithGroupId::fit(events(), 1);
+        assert!(shown.is_empty());
+        assert_eq!(hidden.len(), 4);
     }
 }